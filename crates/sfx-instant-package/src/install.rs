@@ -0,0 +1,230 @@
+use sfx_zip::listing::{EntryInfo, EntryKind};
+use std::{
+    ffi::OsString,
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Replaces a directory without ever leaving it missing or half written.
+///
+/// The new content is written into a sibling staging directory. `commit` moves the
+/// current directory aside, moves staging into place and only then removes the old one.
+/// Dropping an uncommitted transaction, or a failing `commit`, restores the previous state.
+pub struct Transaction {
+    target: PathBuf,
+    staging: PathBuf,
+    previous: PathBuf,
+    committed: bool,
+}
+
+impl Transaction {
+    /// Also recovers from an install that crashed between the two renames of `commit`
+    pub fn begin<P: Into<PathBuf>>(target: P) -> Result<Transaction, Error> {
+        let target = target.into();
+        let transaction = Transaction {
+            staging: sibling(&target, "staging")?,
+            previous: sibling(&target, "previous")?,
+            target,
+            committed: false,
+        };
+
+        if !transaction.target.exists() && transaction.previous.exists() {
+            fs::rename(&transaction.previous, &transaction.target)?;
+        }
+        remove(&transaction.previous)?;
+        remove(&transaction.staging)?;
+        fs::create_dir_all(&transaction.staging)?;
+
+        Ok(transaction)
+    }
+
+    /// Where the new content goes
+    pub fn staging(&self) -> &Path {
+        &self.staging
+    }
+
+    /// Swaps staging into place. On error the previous directory is back.
+    pub fn commit(mut self) -> Result<(), Error> {
+        let had_previous = self.target.exists();
+        if had_previous {
+            fs::rename(&self.target, &self.previous)?;
+        }
+
+        if let Err(error) = fs::rename(&self.staging, &self.target) {
+            if had_previous {
+                fs::rename(&self.previous, &self.target)?;
+            }
+            return Err(error);
+        }
+
+        self.committed = true;
+        // The new version is in place; a leftover previous dir is cleaned by the next install
+        remove(&self.previous)
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = remove(&self.staging);
+        }
+    }
+}
+
+/// `<target>.<suffix>` next to `target`, on the same filesystem so renames stay atomic
+fn sibling(target: &Path, suffix: &str) -> Result<PathBuf, Error> {
+    let mut file_name = OsString::from(target.file_name().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} has no directory name", target),
+        )
+    })?);
+    file_name.push(".");
+    file_name.push(suffix);
+    Ok(target.with_file_name(file_name))
+}
+
+fn remove(path: &Path) -> Result<(), Error> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Copies `paths` of the previous install into the new one, replacing what the payload put there.
+/// Paths the previous install does not have are skipped. Returns the carried paths.
+pub fn carry_over(previous: &Path, staging: &Path, paths: &[String]) -> Result<Vec<String>, Error> {
+    let mut carried = vec![];
+    for path in paths {
+        let from = previous.join(path);
+        if fs::symlink_metadata(&from).is_err() {
+            continue;
+        }
+        let to = staging.join(path);
+        remove(&to)?;
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        copy_recursively(&from, &to)?;
+        carried.push(path.clone());
+    }
+    Ok(carried)
+}
+
+fn copy_recursively(from: &Path, to: &Path) -> Result<(), Error> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else if metadata.file_type().is_symlink() {
+        copy_link(&fs::read_link(from)?, to)?;
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_link(target: &Path, link: &Path) -> Result<(), Error> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn copy_link(target: &Path, link: &Path) -> Result<(), Error> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Checks that `dir` holds every payload entry, files with their full size
+pub fn verify(entries: &[EntryInfo], dir: &Path) -> Result<(), Error> {
+    for entry in entries {
+        let path = dir.join(&entry.path);
+        let metadata = fs::symlink_metadata(&path).map_err(|error| {
+            Error::new(
+                error.kind(),
+                format!("{:?} is missing after extraction: {}", entry.path, error),
+            )
+        })?;
+        let intact = match entry.kind {
+            EntryKind::File => metadata.is_file() && metadata.len() == entry.size,
+            EntryKind::Dir => metadata.is_dir(),
+            EntryKind::Symlink => metadata.file_type().is_symlink(),
+            EntryKind::Hardlink | EntryKind::Special => true,
+        };
+        if !intact {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{:?} does not match the payload after extraction",
+                    entry.path
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn install(target: &Path, previous: Option<&Path>, preserve: &[String]) -> Vec<String> {
+        let transaction = Transaction::begin(target).unwrap();
+        fs::create_dir_all(transaction.staging().join("plugins")).unwrap();
+        fs::write(transaction.staging().join("app.exe"), "app").unwrap();
+        fs::write(transaction.staging().join("config.json"), "{}").unwrap();
+        let carried = match previous {
+            Some(previous) => carry_over(previous, transaction.staging(), preserve).unwrap(),
+            None => vec![],
+        };
+        transaction.commit().unwrap();
+        carried
+    }
+
+    #[test]
+    fn config_survives_a_reinstall() {
+        let root = tempfile::tempdir().unwrap();
+        let v1 = root.path().join("1.0.0");
+        let v2 = root.path().join("2.0.0");
+        let preserve = vec!["config.json".to_string(), "plugins/user".to_string()];
+
+        install(&v1, None, &preserve);
+        fs::write(v1.join("config.json"), "{\"theme\":\"dark\"}").unwrap();
+        fs::create_dir_all(v1.join("plugins/user")).unwrap();
+        fs::write(v1.join("plugins/user/extra.dll"), "extra").unwrap();
+
+        // The same version again: the previous install is the target being replaced
+        assert_eq!(install(&v1, Some(&v1), &preserve), preserve);
+        assert_eq!(
+            fs::read_to_string(v1.join("config.json")).unwrap(),
+            "{\"theme\":\"dark\"}"
+        );
+
+        assert_eq!(install(&v2, Some(&v1), &preserve), preserve);
+        assert_eq!(
+            fs::read_to_string(v2.join("config.json")).unwrap(),
+            "{\"theme\":\"dark\"}"
+        );
+        assert!(v2.join("plugins/user/extra.dll").is_file());
+        assert_eq!(fs::read_to_string(v2.join("app.exe")).unwrap(), "app");
+    }
+
+    #[test]
+    fn missing_paths_are_not_carried() {
+        let root = tempfile::tempdir().unwrap();
+        let v1 = root.path().join("1.0.0");
+        install(&v1, None, &[]);
+
+        let carried = install(
+            &root.path().join("2.0.0"),
+            Some(&v1),
+            &["settings.ini".to_string()],
+        );
+        assert!(carried.is_empty());
+    }
+}
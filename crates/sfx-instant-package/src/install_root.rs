@@ -0,0 +1,137 @@
+use std::{
+    env,
+    ffi::OsString,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Overrides the platform install root, `--install-root` wins over it
+pub const INSTALL_ROOT_ENV: &str = "SFX_INSTANT_INSTALL_ROOT";
+
+/// Reads one environment variable, `std::env::var_os` outside tests
+pub type Lookup<'a> = &'a dyn Fn(&str) -> Option<OsString>;
+
+pub fn process_env(name: &str) -> Option<OsString> {
+    env::var_os(name)
+}
+
+/// Directory that holds one directory per app id.
+///
+/// `--install-root`, then `SFX_INSTANT_INSTALL_ROOT`, then the per-user data directory:
+/// `%LOCALAPPDATA%` on Windows, `~/Library/Application Support` on macOS,
+/// `$XDG_DATA_HOME` or `~/.local/share` elsewhere.
+pub fn resolve(install_root: Option<&Path>) -> Result<PathBuf, Error> {
+    resolve_with(install_root, &process_env)
+}
+
+fn resolve_with(install_root: Option<&Path>, env: Lookup) -> Result<PathBuf, Error> {
+    let install_root = match install_root {
+        Some(install_root) => install_root.to_path_buf(),
+        None => match env_path(env, INSTALL_ROOT_ENV) {
+            Some(install_root) => install_root,
+            None => platform_default(env)?,
+        },
+    };
+    std::path::absolute(install_root)
+}
+
+/// Unset and empty variables are the same
+pub fn env_path(env: Lookup, name: &str) -> Option<PathBuf> {
+    env(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+pub fn required_env_path(env: Lookup, name: &str) -> Result<PathBuf, Error> {
+    env_path(env, name)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{} is not defined", name)))
+}
+
+#[cfg(not(windows))]
+pub fn home(env: Lookup) -> Result<PathBuf, Error> {
+    required_env_path(env, "HOME")
+}
+
+/// `$XDG_DATA_HOME`, else `~/.local/share`
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn data_home(env: Lookup) -> Result<PathBuf, Error> {
+    // The XDG spec says relative values are invalid and must be ignored
+    match env_path(env, "XDG_DATA_HOME").filter(|path| path.is_absolute()) {
+        Some(data_home) => Ok(data_home),
+        None => Ok(home(env)?.join(".local").join("share")),
+    }
+}
+
+#[cfg(windows)]
+fn platform_default(env: Lookup) -> Result<PathBuf, Error> {
+    required_env_path(env, "LOCALAPPDATA")
+}
+
+#[cfg(target_os = "macos")]
+fn platform_default(env: Lookup) -> Result<PathBuf, Error> {
+    Ok(home(env)?.join("Library").join("Application Support"))
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn platform_default(env: Lookup) -> Result<PathBuf, Error> {
+    data_home(env)
+}
+
+#[cfg(all(test, not(any(windows, target_os = "macos"))))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve_in(install_root: Option<&Path>, vars: &[(&str, &Path)]) -> Result<PathBuf, Error> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.as_os_str().to_os_string()))
+            .collect::<HashMap<_, _>>();
+        resolve_with(install_root, &|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn precedence() {
+        let home = Path::new("/home/user");
+        let data_home = Path::new("/data");
+        let from_env = Path::new("/from-env");
+        let from_flag = Path::new("/from-flag");
+        let all = [
+            ("HOME", home),
+            ("XDG_DATA_HOME", data_home),
+            (INSTALL_ROOT_ENV, from_env),
+        ];
+
+        assert_eq!(resolve_in(Some(from_flag), &all).unwrap(), from_flag);
+        assert_eq!(resolve_in(None, &all).unwrap(), from_env);
+
+        let empty_root = [all[0], all[1], (INSTALL_ROOT_ENV, Path::new(""))];
+        assert_eq!(resolve_in(None, &empty_root).unwrap(), data_home);
+
+        let relative_data = [all[0], ("XDG_DATA_HOME", Path::new("relative/data"))];
+        assert_eq!(
+            resolve_in(None, &relative_data).unwrap(),
+            home.join(".local").join("share")
+        );
+        assert_eq!(
+            resolve_in(None, &all[..1]).unwrap(),
+            home.join(".local").join("share")
+        );
+    }
+
+    #[test]
+    fn missing_home() {
+        let error = resolve_in(None, &[]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert_eq!(error.to_string(), "HOME is not defined");
+        // Explicit roots need no home
+        let from_flag = Path::new("/from-flag");
+        assert_eq!(resolve_in(Some(from_flag), &[]).unwrap(), from_flag);
+    }
+
+    #[test]
+    fn relative_roots_are_made_absolute() {
+        let relative = resolve_in(Some(Path::new("apps")), &[]).unwrap();
+        assert_eq!(relative, env::current_dir().unwrap().join("apps"));
+    }
+}
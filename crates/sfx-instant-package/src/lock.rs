@@ -0,0 +1,104 @@
+use fs2::FileExt;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+pub const LOCK_FILE_NAME: &str = "install.lock";
+
+/// Exclusive hold on one app root, so installers of the same app never touch it at once.
+///
+/// The OS releases the lock when its holder exits, crashed or not. The holder's pid is
+/// written into the file and cleared on release, so a pid found on acquiring is the mark
+/// of a crashed install or uninstall.
+pub struct AppLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl AppLock {
+    /// When another process holds the lock, fails unless `wait`, in which case `on_wait`
+    /// gets the holder's pid if known and the call blocks until the lock is free.
+    /// Also returns the pid of a crashed previous holder.
+    pub fn acquire(
+        app_root: &Path,
+        wait: bool,
+        mut on_wait: impl FnMut(Option<u32>),
+    ) -> Result<(AppLock, Option<u32>), Error> {
+        fs::create_dir_all(app_root)?;
+        let path = app_root.join(LOCK_FILE_NAME);
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+
+            if file.try_lock_exclusive().is_err() {
+                let holder = read_pid(&mut file);
+                if !wait {
+                    return Err(Error::new(
+                        ErrorKind::WouldBlock,
+                        match holder {
+                            Some(pid) => {
+                                format!("another installer (pid {}) is working on this app", pid)
+                            }
+                            None => "another installer is working on this app".to_string(),
+                        },
+                    ));
+                }
+                on_wait(holder);
+                file.lock_exclusive()?;
+            }
+
+            // An uninstall removed the file while we waited for it, lock the new one
+            if !is_same_file(&file, &path) {
+                continue;
+            }
+
+            let crashed = read_pid(&mut file);
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            write!(file, "{}", std::process::id())?;
+            file.sync_all()?;
+            return Ok((AppLock { file, path }, crashed));
+        }
+    }
+
+    /// Deletes the lock file so the app root can go, then releases the lock
+    pub fn remove(self) -> Result<(), Error> {
+        fs::remove_file(&self.path)
+    }
+}
+
+impl Drop for AppLock {
+    fn drop(&mut self) {
+        // Cleared pid: a clean release, not a crash
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut pid = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut pid).ok()?;
+    pid.trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(locked), Ok(current)) => locked.dev() == current.dev() && locked.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Without stable file ids a removed lock file is the case to catch
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
@@ -3,58 +3,309 @@ use std::{
     io::Error,
     io::ErrorKind,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use sfx_zip::sfx_ll::{embedder, extractor};
+use sfx_zip::{
+    archive_args::ArchiveArgs,
+    archive_format::{ArchiveFormat, FORMAT_KEY},
+    extract::ExtractOptions,
+    listing::{self, EntryInfo},
+    manifest::{self, Manifest, MANIFEST_KEY},
+    metadata::{AppMetadata, ICON_KEY},
+    progress::{NoProgress, Progress, ProgressBar},
+    sfx_ll::extractor,
+    sources::{self, SourceMapping},
+    SfxZipBuilder,
+};
 use structopt::StructOpt;
 use uuid::{self, Uuid};
 
+mod install;
+mod install_root;
+mod lock;
+mod receipt;
+mod running;
+mod shortcuts;
+mod versions;
+
+use install::Transaction;
+use lock::AppLock;
+use receipt::Receipt;
+use versions::Versions;
+
 #[derive(Debug, StructOpt)]
 struct Opt {
+    /// No progress bar or summaries, only errors
+    #[structopt(short = "q", long, global = true)]
+    quiet: bool,
+    /// Install apps under this directory instead of the per-user data directory
+    /// (also SFX_INSTANT_INSTALL_ROOT)
+    #[structopt(long, global = true)]
+    install_root: Option<PathBuf>,
+    /// Previous versions kept next to the installed one, for rollback
+    #[structopt(long, default_value = "2")]
+    keep_versions: usize,
+    /// Seconds running instances of the app get to exit before install or uninstall gives up
+    #[structopt(long, global = true, default_value = "10")]
+    close_timeout: u64,
+    /// Kill running instances of the app that did not exit in time
+    #[structopt(long, global = true)]
+    force_close: bool,
+    /// Fail instead of waiting when another installer of the app is running
+    #[structopt(long, global = true)]
+    no_wait: bool,
     // Create archive file
     #[structopt(subcommand)]
     archive: Option<Subcommand>,
 }
 
-#[derive(Debug, StructOpt, Clone)]
+#[derive(Debug, StructOpt)]
+struct ArchiveOpt {
+    /// Package described by an sfx.toml; it replaces -a, -s and the archive flags
+    #[structopt(long)]
+    manifest: Option<PathBuf>,
+    #[structopt(
+        short = "a",
+        long,
+        required_unless = "manifest",
+        conflicts_with = "manifest"
+    )]
+    app_id: Option<String>,
+    /// Semantic version being packaged, installed side by side under versions/<version>
+    #[structopt(long, required_unless = "manifest", conflicts_with = "manifest")]
+    app_version: Option<String>,
+    /// Name shown to users, the app id when missing
+    #[structopt(long, conflicts_with = "manifest")]
+    display_name: Option<String>,
+    #[structopt(long, conflicts_with = "manifest")]
+    publisher: Option<String>,
+    /// http(s) URL shown before installing
+    #[structopt(long, conflicts_with = "manifest")]
+    homepage: Option<String>,
+    /// License text file, shown before installing
+    #[structopt(long, conflicts_with = "manifest")]
+    license: Option<PathBuf>,
+    /// .ico file, icon of the installer exe and of the app's shortcuts
+    #[structopt(long, conflicts_with = "manifest")]
+    icon: Option<PathBuf>,
+    /// Directory or file to package, repeatable.
+    /// `path=prefix` puts it under prefix inside the app directory.
+    #[structopt(
+        short = "s",
+        long,
+        number_of_values = 1,
+        required_unless = "manifest",
+        conflicts_with = "manifest"
+    )]
+    source: Vec<SourceMapping>,
+    #[structopt(short = "w", long)]
+    workspace: PathBuf,
+    #[structopt(short = "d", long)]
+    destination: PathBuf,
+    #[structopt(short = "f", long)]
+    force: bool,
+    /// File or directory of the installed app carried into the next version on upgrade,
+    /// repeatable
+    #[structopt(long, number_of_values = 1, conflicts_with = "manifest")]
+    preserve: Vec<String>,
+    #[structopt(flatten)]
+    archive_args: ArchiveArgs,
+}
+
+#[derive(Debug, StructOpt)]
 enum Subcommand {
-    Archive {
+    Archive(Box<ArchiveOpt>),
+    /// Print the payload's entries without installing anything
+    List {
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Point `current` back to a previously installed version
+    Rollback {
+        /// Defaults to the app id of this installer
         #[structopt(short = "a", long)]
-        app_id: String,
-        #[structopt(short = "s", long)]
-        source: PathBuf,
-        #[structopt(short = "w", long)]
-        workspace: PathBuf,
-        #[structopt(short = "d", long)]
-        destination: PathBuf,
-        #[structopt(short = "f", long)]
-        force: bool,
+        app_id: Option<String>,
+        /// Defaults to the version installed before the current one
+        #[structopt(long)]
+        to: Option<String>,
+    },
+    /// Remove what the installs of an app created, as recorded in their receipts
+    Uninstall {
+        /// Defaults to the app id of this installer
+        #[structopt(short = "a", long)]
+        app_id: Option<String>,
+        /// Also remove the user data directory
+        #[structopt(long)]
+        purge: bool,
     },
 }
 
 const FLAG_IS_ARCHIVE: &str = "SFX_INSTANT_FLAG__PACKAGE_ARCHIVE";
 const FLAG_APP_ID: &str = "SFX_INSTANT_FLAG__APP_ID";
+const FLAG_VERSION: &str = "SFX_INSTANT_FLAG__VERSION";
+/// Paths carried over on upgrade, one per line
+const FLAG_PRESERVE: &str = "SFX_INSTANT_FLAG__PRESERVE";
 const INSTALLER_WORKSPACE_SUBPATH: &str = ".sfx_instant_installer_workspace";
-const APP_DIR_SUBPATH: &str = ".sfx_app";
+/// Single install dir of installers without versions
+const LEGACY_APP_DIR_SUBPATH: &str = ".sfx_app";
+/// Copy of the embedded icon, shortcuts point to it
+const ICON_FILE_NAME: &str = "icon.ico";
+/// Belongs to the user rather than to any version: upgrades never touch it
+/// and uninstall keeps it unless purging. Apps reach it as `../../data` from their
+/// install dir, see the README.
+const USER_DATA_SUBPATH: &str = "data";
+/// Under the user data directory: preserved paths of an uninstalled app,
+/// carried over by the next install
+const KEPT_PRESERVED_SUBPATH: &str = ".sfx-preserved";
 
 fn main() {
     let opt = Opt::from_args();
 
+    let close = CloseRunning {
+        timeout: Duration::from_secs(opt.close_timeout),
+        force: opt.force_close,
+        quiet: opt.quiet,
+    };
     match opt.archive {
-        Some(archive_opt) => handle_archive(archive_opt),
-        None => handle_extract(),
+        Some(Subcommand::List { json }) => handle_list(json),
+        Some(Subcommand::Rollback { app_id, to }) => handle_rollback(
+            opt.install_root.as_deref(),
+            app_id,
+            to,
+            opt.no_wait,
+            opt.quiet,
+        ),
+        Some(Subcommand::Uninstall { app_id, purge }) => handle_uninstall(
+            opt.install_root.as_deref(),
+            app_id,
+            purge,
+            &close,
+            opt.no_wait,
+            opt.quiet,
+        ),
+        Some(Subcommand::Archive(archive_opt)) => handle_archive(*archive_opt, opt.quiet),
+        None => handle_extract(
+            opt.install_root.as_deref(),
+            opt.keep_versions,
+            &close,
+            opt.no_wait,
+            opt.quiet,
+        ),
+    }
+}
+
+/// How to deal with instances of the app running while it is installed or removed
+struct CloseRunning {
+    timeout: Duration,
+    force: bool,
+    quiet: bool,
+}
+
+impl CloseRunning {
+    /// Closes every process running from `app_root`, printing what happened.
+    /// `false` when some are still running and the caller must not touch `app_root`.
+    /// Where processes cannot be listed it warns and lets the caller go on.
+    fn run(&self, app_root: &Path, action: &str) -> bool {
+        let report = match running::close(app_root, self.timeout, self.force) {
+            Ok(report) => report,
+            Err(error) if error.kind() == ErrorKind::Unsupported => {
+                eprintln!(
+                    "warning: cannot check for running instances of the app. {}",
+                    error
+                );
+                return true;
+            }
+            Err(error) => {
+                eprintln!("Cannot {}. Cannot close the running app. {}", action, error);
+                return false;
+            }
+        };
+
+        if !self.quiet {
+            report.closed.iter().for_each(|process| {
+                println!("closed running {:?} (pid {})", process.exe, process.pid)
+            });
+        }
+        report.killed.iter().for_each(|process| {
+            eprintln!("killed running {:?} (pid {})", process.exe, process.pid)
+        });
+        if report.still_running.is_empty() {
+            return true;
+        }
+
+        eprintln!(
+            "Cannot {}. {} instance(s) of the app are still running after {}s:",
+            action,
+            report.still_running.len(),
+            self.timeout.as_secs()
+        );
+        report
+            .still_running
+            .iter()
+            .for_each(|process| eprintln!("  {:?} (pid {})", process.exe, process.pid));
+        if !self.force {
+            eprintln!("Close them, or pass --force-close to kill them");
+        }
+        false
+    }
+}
+
+/// Locks the app root against other installers of the app for as long as the lock lives
+fn lock_app(app_root: &Path, no_wait: bool, quiet: bool, action: &str) -> Option<AppLock> {
+    let wait_message = |holder: Option<u32>| {
+        if !quiet {
+            match holder {
+                Some(pid) => eprintln!("waiting for another installer (pid {}) to finish", pid),
+                None => eprintln!("waiting for another installer to finish"),
+            }
+        }
+    };
+    match AppLock::acquire(app_root, !no_wait, wait_message) {
+        Ok((lock, crashed)) => {
+            if let Some(pid) = crashed {
+                eprintln!(
+                    "warning: a previous install or uninstall (pid {}) was interrupted, recovering",
+                    pid
+                );
+            }
+            Some(lock)
+        }
+        Err(error) if error.kind() == ErrorKind::WouldBlock => {
+            eprintln!(
+                "Cannot {}. {}, retry without --no-wait to wait for it",
+                action, error
+            );
+            None
+        }
+        Err(error) => {
+            eprintln!("Cannot {}. Cannot lock {:?}. {}", action, app_root, error);
+            None
+        }
     }
 }
 
-fn handle_extract() {
-    let flag_is_archive_in_this_exe =
-        sfx_zip::sfx_ll::extractor::read_custom_string(&String::from(FLAG_IS_ARCHIVE));
-    if let None = flag_is_archive_in_this_exe {
+fn progress_for(quiet: bool) -> Box<dyn Progress> {
+    if quiet {
+        Box::new(NoProgress)
+    } else {
+        Box::new(ProgressBar::new())
+    }
+}
+
+fn handle_extract(
+    install_root: Option<&Path>,
+    keep_versions: usize,
+    close: &CloseRunning,
+    no_wait: bool,
+    quiet: bool,
+) {
+    let flag_is_archive_in_this_exe = extractor::read_custom_string(FLAG_IS_ARCHIVE);
+    if flag_is_archive_in_this_exe.is_none() {
         eprintln!("Cannot extract. Not archive");
         return;
     }
-    let app_id_in_this_exe =
-        sfx_zip::sfx_ll::extractor::read_custom_string(&String::from(FLAG_APP_ID));
+    let app_id_in_this_exe = extractor::read_custom_string(FLAG_APP_ID);
 
     let app_id_in_this_exe = match app_id_in_this_exe {
         Some(x) => x,
@@ -63,82 +314,685 @@ fn handle_extract() {
             return;
         }
     };
+    // Joined to the install root, `..` or an absolute path would escape it
+    if let Err(message) = manifest::check_app_id(&app_id_in_this_exe) {
+        eprintln!(
+            "Cannot extract. app id {:?} {}",
+            app_id_in_this_exe, message
+        );
+        return;
+    }
 
-    let local_app_data_path = match std::env::var("LOCALAPPDATA") {
-        Ok(string) => PathBuf::from(string),
-        Err(_) => {
-            eprintln!("Cannot install. LOCALAPPDATA is not defined");
+    let version = match extractor::read_custom_string(FLAG_VERSION) {
+        Some(version) => version,
+        None => {
+            eprintln!("Cannot extract. No version");
             return;
         }
     };
+    if let Err(error) = versions::validate_version(&version) {
+        eprintln!("Cannot extract. {}", error);
+        return;
+    }
 
-    let root_app_path = local_app_data_path.join(app_id_in_this_exe);
+    let preserve = extractor::read_custom_string(FLAG_PRESERVE)
+        .map(|preserve| preserve.lines().map(String::from).collect::<Vec<_>>())
+        .unwrap_or_default();
+    for path in &preserve {
+        if let Err(message) = sources::check_archive_path(path) {
+            eprintln!("Cannot extract. preserve path {:?} {}", path, message);
+            return;
+        }
+    }
+
+    // Installers built before metadata existed have none
+    let metadata = match AppMetadata::read() {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            eprintln!("Cannot extract. {}", error);
+            return;
+        }
+    };
+    if let (Some(metadata), false) = (&metadata, quiet) {
+        print_metadata(&app_id_in_this_exe, metadata);
+    }
+
+    let install_root_path = match install_root::resolve(install_root) {
+        Ok(install_root_path) => install_root_path,
+        Err(error) => {
+            eprintln!("Cannot install. {}", error);
+            return;
+        }
+    };
+
+    let root_app_path = install_root_path.join(&app_id_in_this_exe);
     let installer_workspace_path = root_app_path.join(INSTALLER_WORKSPACE_SUBPATH);
-    let app_dir_path = root_app_path.join(APP_DIR_SUBPATH);
+    let versions = Versions::new(&root_app_path);
+    let app_dir_path = versions.dir(&version);
+
+    let format = match ArchiveFormat::from_metadata(extractor::read_custom_string(FORMAT_KEY)) {
+        Ok(format) => format,
+        Err(error) => {
+            eprintln!("Cannot extract. {}", error);
+            return;
+        }
+    };
 
-    // TEST installer_path first
+    // Held until the install is done, released when dropped
+    let _lock = match lock_app(&root_app_path, no_wait, quiet, "install") {
+        Some(lock) => lock,
+        None => return,
+    };
+    if !close.run(&root_app_path, "install") {
+        return;
+    }
 
-    ensure_gone(&app_dir_path);
+    // Left behind by installers that extracted through a temporary zip
     ensure_gone(&installer_workspace_path);
-    fs::create_dir_all(&installer_workspace_path).unwrap();
 
-    let zip_file_path = installer_workspace_path.join({
-        let mut path = PathBuf::from(Uuid::new_v4().to_string());
-        path.set_extension("zip");
-        path
+    let transaction = match Transaction::begin(&app_dir_path) {
+        Ok(transaction) => transaction,
+        Err(error) => {
+            eprintln!("Cannot install. {}", error);
+            return;
+        }
+    };
+
+    // Dropping the transaction removes the staging dir, the current install stays as it was
+    let receipt = extract_verified(format, transaction.staging(), quiet).and_then(|entries| {
+        Receipt::new(
+            &app_id_in_this_exe,
+            &version,
+            &app_dir_path,
+            &entries,
+            transaction.staging(),
+        )
     });
+    let kept_preserved_path = root_app_path
+        .join(USER_DATA_SUBPATH)
+        .join(KEPT_PRESERVED_SUBPATH);
+    let previous_dir = match versions.current() {
+        Ok(Some(current)) => Some(versions.dir(&current)),
+        Ok(None) => [
+            root_app_path.join(LEGACY_APP_DIR_SUBPATH),
+            kept_preserved_path.clone(),
+        ]
+        .into_iter()
+        .find(|previous_dir| previous_dir.is_dir()),
+        Err(error) => {
+            eprintln!("Cannot install. {}", error);
+            return;
+        }
+    }
+    .filter(|previous_dir| previous_dir.is_dir());
+    let carried = receipt.and_then(|receipt| match &previous_dir {
+        Some(previous_dir) => install::carry_over(previous_dir, transaction.staging(), &preserve)
+            .map(|carried| (receipt, carried)),
+        None => Ok((receipt, vec![])),
+    });
+    let (mut receipt, carried) = match carried {
+        Ok(carried) => carried,
+        Err(error) => {
+            eprintln!(
+                "Cannot install. {}. The previous install is left untouched",
+                error
+            );
+            return;
+        }
+    };
 
-    extractor::extract_binary(&zip_file_path).unwrap();
-    sfx_zip::zip_fns::extract(&zip_file_path, &app_dir_path);
-    ensure_gone(&installer_workspace_path);
+    if let Err(error) = transaction.commit() {
+        eprintln!(
+            "Cannot install. {}. Rolled back to the previous install",
+            error
+        );
+        return;
+    }
+
+    if let Err(error) = versions.record_install(&version) {
+        eprintln!("Cannot install. {}", error);
+        return;
+    }
+    if let Err(error) = fs::create_dir_all(root_app_path.join(USER_DATA_SUBPATH)) {
+        eprintln!(
+            "Installed, but cannot create the user data directory. {}",
+            error
+        );
+    }
+    let icon_path = root_app_path.join(ICON_FILE_NAME);
+    let icon_path = match extractor::read_custom_data(ICON_KEY) {
+        Some(icon) => match fs::write(&icon_path, icon) {
+            Ok(()) => Some(icon_path),
+            Err(error) => {
+                eprintln!("warning: cannot write the app icon. {}", error);
+                None
+            }
+        },
+        None => {
+            // Left by a previous version
+            ensure_gone(&icon_path);
+            None
+        }
+    };
+    receipt.set_preserved(carried);
+    receipt.metadata = metadata.clone();
+    receipt.shortcut_definitions = manifest_shortcuts();
+    receipt.shortcuts = create_shortcuts(
+        &app_id_in_this_exe,
+        &receipt.shortcut_definitions,
+        &app_dir_path,
+        metadata.as_ref(),
+        icon_path.as_deref(),
+    );
+
+    if let Err(error) = receipt.save(&root_app_path) {
+        eprintln!(
+            "Installed, but cannot write the receipt, uninstall will miss this version. {}",
+            error
+        );
+    }
+    // Replaced by versions/, nothing reads it anymore
+    ensure_gone(root_app_path.join(LEGACY_APP_DIR_SUBPATH));
+    // Carried over into this install
+    ensure_gone(&kept_preserved_path);
+
+    let removed = match versions.prune(keep_versions) {
+        Ok(removed) => {
+            for version in &removed {
+                ensure_gone(Receipt::path(&root_app_path, version));
+            }
+            removed
+        }
+        Err(error) => {
+            eprintln!("Installed, but cannot remove old versions. {}", error);
+            vec![]
+        }
+    };
+    if !quiet {
+        println!(
+            "installed {} {} into {:?}",
+            app_id_in_this_exe, version, app_dir_path
+        );
+        println!(
+            "  user data directory {:?}",
+            root_app_path.join(USER_DATA_SUBPATH)
+        );
+        receipt
+            .preserved
+            .iter()
+            .for_each(|path| println!("  kept {} from the previous install", path));
+        receipt
+            .shortcuts
+            .iter()
+            .for_each(|path| println!("  created shortcut {:?}", path));
+        removed
+            .iter()
+            .for_each(|version| println!("  removed old version {}", version));
+    }
+}
+
+/// What is about to be installed
+fn print_metadata(app_id: &str, metadata: &AppMetadata) {
+    println!(
+        "{} {} ({})",
+        metadata.display_name.as_deref().unwrap_or(app_id),
+        metadata.version,
+        app_id
+    );
+    if let Some(publisher) = &metadata.publisher {
+        println!("  publisher: {}", publisher);
+    }
+    if let Some(homepage) = &metadata.homepage {
+        println!("  homepage: {}", homepage);
+    }
+    if let Some(license) = &metadata.license {
+        println!("  license:");
+        license.lines().for_each(|line| println!("    {}", line));
+    }
 }
 
-fn handle_archive(opt: Subcommand) {
-    let Subcommand::Archive {
+/// Shortcuts the embedded manifest declares
+fn manifest_shortcuts() -> Vec<shortcuts::Definition> {
+    match extractor::read_custom_string(MANIFEST_KEY)
+        .map(|manifest| Manifest::from_normalized(&manifest))
+    {
+        Some(Ok(manifest)) => manifest.shortcuts.iter().map(Into::into).collect(),
+        Some(Err(error)) => {
+            eprintln!("warning: cannot create shortcuts. {}", error);
+            vec![]
+        }
+        None => vec![],
+    }
+}
+
+/// `definitions` pointing into `app_dir`.
+/// Failing ones are only warned about, the app is installed either way.
+fn create_shortcuts(
+    app_id: &str,
+    definitions: &[shortcuts::Definition],
+    app_dir: &Path,
+    metadata: Option<&AppMetadata>,
+    icon: Option<&Path>,
+) -> Vec<PathBuf> {
+    let description = metadata.and_then(|metadata| metadata.display_name.as_deref());
+    definitions
+        .iter()
+        .filter_map(|shortcut| {
+            match shortcuts::create(app_id, shortcut, app_dir, description, icon) {
+                Ok(path) => Some(path),
+                Err(error) => {
+                    eprintln!(
+                        "warning: cannot create shortcut {:?}. {}",
+                        shortcut.name, error
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Replaces the shortcuts of the `from` install by those of `to`, so they launch
+/// the version in use. Returns the created shortcuts.
+fn switch_shortcuts(
+    root_app_path: &Path,
+    app_id: &str,
+    from: Option<&str>,
+    to: &str,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut to_receipt = Receipt::load(&Receipt::path(root_app_path, to))?;
+    if let Some(from) = from.filter(|from| *from != to) {
+        let mut from_receipt = Receipt::load(&Receipt::path(root_app_path, from))?;
+        for path in from_receipt.shortcuts.drain(..) {
+            if path.is_file() {
+                fs::remove_file(path)?;
+            }
+        }
+        from_receipt.save(root_app_path)?;
+    }
+
+    // The icon is the one of the latest install, versions do not keep their own
+    let icon_path = Some(root_app_path.join(ICON_FILE_NAME)).filter(|path| path.is_file());
+    to_receipt.shortcuts = create_shortcuts(
         app_id,
+        &to_receipt.shortcut_definitions,
+        &to_receipt.install_dir,
+        to_receipt.metadata.as_ref(),
+        icon_path.as_deref(),
+    );
+    to_receipt.save(root_app_path)?;
+    Ok(to_receipt.shortcuts)
+}
+
+fn handle_rollback(
+    install_root: Option<&Path>,
+    app_id: Option<String>,
+    to: Option<String>,
+    no_wait: bool,
+    quiet: bool,
+) {
+    let app_id = match app_id.or_else(|| extractor::read_custom_string(FLAG_APP_ID)) {
+        Some(app_id) => app_id,
+        None => {
+            eprintln!("Cannot rollback. No app_id, pass --app-id");
+            return;
+        }
+    };
+    if let Err(message) = manifest::check_app_id(&app_id) {
+        eprintln!("Cannot rollback. app id {:?} {}", app_id, message);
+        return;
+    }
+    let install_root_path = match install_root::resolve(install_root) {
+        Ok(install_root_path) => install_root_path,
+        Err(error) => {
+            eprintln!("Cannot rollback. {}", error);
+            return;
+        }
+    };
+
+    let root_app_path = install_root_path.join(&app_id);
+    if !root_app_path.is_dir() {
+        eprintln!("Cannot rollback. {} is not installed", app_id);
+        return;
+    }
+    let _lock = match lock_app(&root_app_path, no_wait, quiet, "rollback") {
+        Some(lock) => lock,
+        None => return,
+    };
+
+    let versions = Versions::new(&root_app_path);
+    let result = versions.rollback_target(to.as_deref()).and_then(|version| {
+        let previous = versions.current()?;
+        versions.set_current(&version)?;
+        Ok((previous, version))
+    });
+    let (previous, version) = match result {
+        Ok(rollback) => rollback,
+        Err(error) => {
+            eprintln!("Cannot rollback. {}", error);
+            return;
+        }
+    };
+    let shortcuts = match switch_shortcuts(&root_app_path, &app_id, previous.as_deref(), &version) {
+        Ok(shortcuts) => shortcuts,
+        Err(error) => {
+            eprintln!(
+                "Rolled back, but cannot update the shortcuts, they may still launch {}. {}",
+                previous.as_deref().unwrap_or("the previous version"),
+                error
+            );
+            vec![]
+        }
+    };
+    if !quiet {
+        println!("{} now uses version {}", app_id, version);
+        shortcuts
+            .iter()
+            .for_each(|path| println!("  updated shortcut {:?}", path));
+    }
+}
+
+/// Extracts the payload into `destination` and checks the result against the payload listing
+fn extract_verified(
+    format: ArchiveFormat,
+    destination: &Path,
+    quiet: bool,
+) -> Result<Vec<EntryInfo>, Error> {
+    format.extract_with_options(
+        extractor::PayloadReader::new()?,
+        destination,
+        &ExtractOptions::default(),
+        progress_for(quiet).as_mut(),
+    )?;
+    let entries = format.list_from_reader(extractor::PayloadReader::new()?)?;
+    install::verify(&entries, destination)?;
+    Ok(entries)
+}
+
+fn handle_uninstall(
+    install_root: Option<&Path>,
+    app_id: Option<String>,
+    purge: bool,
+    close: &CloseRunning,
+    no_wait: bool,
+    quiet: bool,
+) {
+    let app_id = match app_id.or_else(|| extractor::read_custom_string(FLAG_APP_ID)) {
+        Some(app_id) => app_id,
+        None => {
+            eprintln!("Cannot uninstall. No app_id, pass --app-id");
+            return;
+        }
+    };
+    if let Err(message) = manifest::check_app_id(&app_id) {
+        eprintln!("Cannot uninstall. app id {:?} {}", app_id, message);
+        return;
+    }
+    let install_root_path = match install_root::resolve(install_root) {
+        Ok(install_root_path) => install_root_path,
+        Err(error) => {
+            eprintln!("Cannot uninstall. {}", error);
+            return;
+        }
+    };
+
+    let root_app_path = install_root_path.join(&app_id);
+    if !root_app_path.is_dir() {
+        eprintln!("Cannot uninstall. {} is not installed", app_id);
+        return;
+    }
+
+    let lock = match lock_app(&root_app_path, no_wait, quiet, "uninstall") {
+        Some(lock) => lock,
+        None => return,
+    };
+    if !close.run(&root_app_path, "uninstall") {
+        return;
+    }
+
+    let receipts = match Receipt::load_all(&root_app_path) {
+        Ok(receipts) => receipts,
+        Err(error) => {
+            eprintln!("Cannot uninstall. {}", error);
+            return;
+        }
+    };
+
+    // Only the version in use has the preserved paths the next install should get back
+    let current = Versions::new(&root_app_path).current().ok().flatten();
+    let user_data_path = root_app_path.join(USER_DATA_SUBPATH);
+    let kept_preserved_path = user_data_path.join(KEPT_PRESERVED_SUBPATH);
+
+    let mut removed_files = 0;
+    let mut missing_files = 0;
+    for (receipt_path, receipt) in receipts {
+        let keep_preserved = (!purge && current.as_ref() == Some(&receipt.version))
+            .then_some(kept_preserved_path.as_path());
+        let report = match receipt.uninstall(keep_preserved) {
+            Ok(report) => report,
+            Err(error) => {
+                eprintln!("Cannot uninstall version {}. {}", receipt.version, error);
+                return;
+            }
+        };
+        removed_files += report.removed_files;
+        missing_files += report.missing.len();
+        report.modified.iter().for_each(|path| {
+            eprintln!("warning: {:?} was modified after install, kept", path);
+        });
+        ensure_gone(receipt_path);
+    }
+
+    if let Err(error) = Versions::new(&root_app_path).forget() {
+        eprintln!("warning: {}", error);
+    }
+    ensure_gone(root_app_path.join(INSTALLER_WORKSPACE_SUBPATH));
+    ensure_gone(root_app_path.join(ICON_FILE_NAME));
+    let _ = receipt::remove_dir_if_empty(&root_app_path.join(receipt::RECEIPTS_SUBPATH));
+
+    if purge {
+        ensure_gone(&user_data_path);
+    } else if let Ok(false) = receipt::remove_dir_if_empty(&user_data_path) {
+        eprintln!(
+            "kept user data in {:?}, uninstall --purge removes it",
+            user_data_path
+        );
+    }
+
+    if let Err(error) = lock.remove() {
+        eprintln!("warning: cannot remove the lock file. {}", error);
+    }
+    match receipt::remove_dir_if_empty(&root_app_path) {
+        Ok(true) => {}
+        Ok(false) => eprintln!(
+            "warning: {:?} still holds files no install created, left in place",
+            root_app_path
+        ),
+        Err(error) => eprintln!("warning: cannot remove {:?}. {}", root_app_path, error),
+    }
+    if !quiet {
+        println!("uninstalled {}, removed {} file(s)", app_id, removed_files);
+        if missing_files > 0 {
+            println!("  {} file(s) were already gone", missing_files);
+        }
+    }
+}
+
+fn handle_list(json: bool) {
+    let flag_is_archive_in_this_exe = extractor::read_custom_string(FLAG_IS_ARCHIVE);
+    if flag_is_archive_in_this_exe.is_none() {
+        eprintln!("Cannot list. Not archive");
+        return;
+    }
+
+    let format = match ArchiveFormat::from_metadata(extractor::read_custom_string(FORMAT_KEY)) {
+        Ok(format) => format,
+        Err(error) => {
+            eprintln!("Cannot list. {}", error);
+            return;
+        }
+    };
+
+    let payload_reader = match extractor::PayloadReader::new() {
+        Ok(payload_reader) => payload_reader,
+        Err(error) => {
+            eprintln!("Cannot list. {}", error);
+            return;
+        }
+    };
+    match format.list_from_reader(payload_reader) {
+        Ok(entries) => listing::print(&entries, json),
+        Err(error) => eprintln!("Cannot list. {}", error),
+    }
+}
+
+fn handle_archive(opt: ArchiveOpt, quiet: bool) {
+    let parameters = format!("{:?}", opt);
+    let ArchiveOpt {
+        manifest,
+        app_id,
+        app_version,
+        display_name,
+        publisher,
+        homepage,
+        license,
+        icon,
         source,
         workspace,
         destination,
         force,
-    } = opt.clone();
+        preserve,
+        archive_args,
+    } = opt;
 
     let mut errors: Vec<std::io::Error> = vec![];
 
-    let flag_is_archive_in_this_exe =
-        sfx_zip::sfx_ll::extractor::read_custom_string(&String::from(FLAG_IS_ARCHIVE));
+    let manifest = match manifest {
+        Some(manifest) => match Manifest::load(manifest) {
+            Ok(manifest) => Some(manifest),
+            Err(manifest_errors) => {
+                errors.extend(manifest_errors);
+                None
+            }
+        },
+        None => None,
+    };
+    let (app_id, version, preserve, sources, format, archive_options) = match &manifest {
+        Some(manifest) => (
+            manifest.app.id.get_ref().clone(),
+            manifest.app.version.get_ref().clone(),
+            manifest.preserve(),
+            manifest.source_mappings(),
+            manifest.format(),
+            manifest.archive_options(),
+        ),
+        None => (
+            app_id.unwrap_or_default(),
+            app_version.unwrap_or_default(),
+            preserve,
+            source,
+            archive_args.format,
+            archive_args.archive_options(),
+        ),
+    };
+
+    let (mut metadata, icon) = match &manifest {
+        Some(manifest) => (manifest.metadata(), manifest.icon()),
+        None => (
+            AppMetadata {
+                display_name,
+                version: version.clone(),
+                publisher,
+                homepage,
+                license: None,
+            },
+            icon,
+        ),
+    };
+
+    let flag_is_archive_in_this_exe = extractor::read_custom_string(FLAG_IS_ARCHIVE);
 
-    if let Some(_) = flag_is_archive_in_this_exe {
+    if flag_is_archive_in_this_exe.is_some() {
         eprintln!("Cannot use this exe to archive. This exe is already an archive");
         return;
     }
 
-    if !source.is_dir() {
-        errors.push(Error::new(ErrorKind::Other, "Source is not directory"));
+    // Nothing to check when the manifest did not load
+    if errors.is_empty() {
+        if let Err(message) = manifest::check_app_id(&app_id) {
+            errors.push(Error::new(
+                ErrorKind::InvalidInput,
+                format!("app id {:?} {}", app_id, message),
+            ));
+        }
+        // Checks the version with versions::validate_version's rules
+        errors.extend(metadata.validate());
+    }
+
+    if let Some(license) = license {
+        match fs::read_to_string(&license) {
+            Ok(license) => metadata.license = Some(license),
+            Err(error) => errors.push(Error::new(
+                error.kind(),
+                format!("Cannot read license {:?}. {}", license, error),
+            )),
+        }
+    }
+
+    if let Some(icon) = &icon {
+        if !icon.is_file() {
+            errors.push(Error::other("Icon is not a file"));
+        }
+    }
+
+    for path in &preserve {
+        if let Err(message) = sources::check_archive_path(path) {
+            errors.push(Error::new(
+                ErrorKind::InvalidInput,
+                format!("preserve path {:?} {}", path, message),
+            ));
+        }
+    }
+
+    for source in &sources {
+        errors.extend(source.validate());
     }
 
     if !workspace.is_dir() {
-        errors.push(Error::new(ErrorKind::Other, "Workspace is not directory"));
+        errors.push(Error::other("Workspace is not directory"));
     }
 
     if destination.exists() && !force {
-        errors.push(Error::new(ErrorKind::Other, "Destination already exist"));
+        errors.push(Error::other("Destination already exist"));
+    }
+
+    if let Err(error) = archive_options.compression.validate(format) {
+        errors.push(error);
     }
 
-    if errors.len() > 0 {
+    if !errors.is_empty() {
         eprintln!("{} error occured", errors.len());
         errors.iter().for_each(|error| {
             eprintln!("error: {}", error);
         });
-        eprintln!("parameters {:?}", &opt);
+        eprintln!("parameters {}", parameters);
         return;
     }
 
-    let temp_zip_path = workspace.join({
+    let temp_zip_path = if archive_options.reproducible {
+        // Keep every input of the build deterministic.
+        // App ids contain dots, so the suffix is appended rather than set as an extension.
+        let temp_file_path = workspace.join(format!("{}.sfx-instant-package.zip", app_id));
+        if temp_file_path.exists() {
+            eprintln!("{:?} already exists in workspace", temp_file_path);
+            return;
+        }
+        temp_file_path
+    } else {
         let unlucky_limit = 15;
         let mut index: usize = 0;
         loop {
-            let mut temp_file_path = PathBuf::from(Uuid::new_v4().to_string());
-            temp_file_path.set_extension("zip");
+            let temp_file_path = workspace.join(format!("{}.zip", Uuid::new_v4()));
             if !temp_file_path.exists() {
                 break temp_file_path;
             }
@@ -149,35 +1003,37 @@ fn handle_archive(opt: Subcommand) {
                 return;
             }
         }
-    });
-
-    sfx_zip::zip_fns::archive(source, temp_zip_path.clone());
+    };
 
-    // TODO: kill app-id first
     // TODO: code signing
 
-    ensure_gone(&destination);
-    fs::copy(std::env::current_exe().unwrap(), &destination).unwrap();
-
-    sfx_zip::sfx_ll::embedder::with_resource_update_handle(
-        &destination,
-        Box::new(|handle| {
-            embedder::embed_binary_as_archive(handle, &temp_zip_path.as_path()).unwrap();
-
-            embedder::embed_custom_string(
-                handle,
-                &String::from(FLAG_IS_ARCHIVE),
-                &String::from(FLAG_IS_ARCHIVE),
-            );
+    let mut builder = match &manifest {
+        Some(manifest) => SfxZipBuilder::from_manifest(manifest, destination),
+        None => SfxZipBuilder::from_sources(sources, destination)
+            .format(format)
+            .options(archive_options)
+            .metadata(metadata),
+    }
+    .temp_payload(temp_zip_path)
+    .force(force)
+    .custom_string(FLAG_IS_ARCHIVE, FLAG_IS_ARCHIVE)
+    .custom_string(FLAG_APP_ID, app_id)
+    .custom_string(FLAG_VERSION, version);
+    if !preserve.is_empty() {
+        builder = builder.custom_string(FLAG_PRESERVE, preserve.join("\n"));
+    }
+    if let Some(icon) = icon {
+        builder = builder.icon(icon);
+    }
 
-            embedder::embed_custom_string(
-                handle,
-                &String::from(FLAG_APP_ID),
-                &String::from(app_id),
-            );
-        }),
-    );
-    ensure_gone(temp_zip_path);
+    match builder.build(progress_for(quiet).as_mut()) {
+        Ok(skip_summary) => {
+            if !quiet {
+                skip_summary.print();
+            }
+        }
+        Err(error) => eprintln!("Cannot archive. {}", error),
+    }
 }
 
 fn ensure_gone<P: AsRef<Path>>(filepath: P) {
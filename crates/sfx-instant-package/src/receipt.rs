@@ -0,0 +1,378 @@
+use crate::shortcuts::Definition;
+use serde::{Deserialize, Serialize};
+use sfx_zip::{
+    listing::{EntryInfo, EntryKind},
+    metadata::AppMetadata,
+    onefile,
+    progress::NoProgress,
+};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+pub const RECEIPTS_SUBPATH: &str = "receipts";
+
+/// What one install put on disk, so uninstall removes exactly that
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Receipt {
+    pub app_id: String,
+    pub version: String,
+    pub install_dir: PathBuf,
+    /// As embedded in the installer, missing for installers built without it
+    #[serde(default)]
+    pub metadata: Option<AppMetadata>,
+    /// Relative to `install_dir`
+    pub files: Vec<InstalledFile>,
+    /// Relative to `install_dir`
+    #[serde(default)]
+    pub links: Vec<String>,
+    /// Relative to `install_dir`
+    #[serde(default)]
+    pub dirs: Vec<String>,
+    /// Carried over from the previous install, relative to `install_dir`. Their content is
+    /// the user's, not the payload's, so it is not hashed and uninstall treats them as user data.
+    #[serde(default)]
+    pub preserved: Vec<String>,
+    #[serde(default)]
+    pub shortcuts: Vec<PathBuf>,
+    /// What `shortcuts` are created from, including those that failed
+    #[serde(default)]
+    pub shortcut_definitions: Vec<Definition>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstalledFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Default)]
+pub struct UninstallReport {
+    pub removed_files: usize,
+    /// Changed since install, kept
+    pub modified: Vec<PathBuf>,
+    /// Already gone
+    pub missing: Vec<PathBuf>,
+    /// Preserved paths moved out of the install, where they are now
+    pub kept: Vec<PathBuf>,
+}
+
+impl Receipt {
+    /// Hashes the installed payload entries, `dir` being where they are now
+    pub fn new(
+        app_id: &str,
+        version: &str,
+        install_dir: &Path,
+        entries: &[EntryInfo],
+        dir: &Path,
+    ) -> Result<Receipt, Error> {
+        let mut receipt = Receipt {
+            app_id: app_id.to_string(),
+            version: version.to_string(),
+            install_dir: install_dir.to_path_buf(),
+            metadata: None,
+            files: vec![],
+            links: vec![],
+            dirs: vec![],
+            preserved: vec![],
+            shortcuts: vec![],
+            shortcut_definitions: vec![],
+        };
+
+        for entry in entries {
+            let path = entry.path.trim_end_matches('/').to_string();
+            match entry.kind {
+                EntryKind::File => receipt.files.push(InstalledFile {
+                    sha256: onefile::hash_file(dir.join(&path), &mut NoProgress)?,
+                    size: entry.size,
+                    path,
+                }),
+                EntryKind::Symlink => receipt.links.push(path),
+                EntryKind::Dir => receipt.dirs.push(path),
+                EntryKind::Hardlink | EntryKind::Special => {}
+            }
+        }
+
+        Ok(receipt)
+    }
+
+    /// Records `carried` as preserved, in place of the payload entries they replaced
+    pub fn set_preserved(&mut self, carried: Vec<String>) {
+        let replaced = |path: &String| {
+            carried
+                .iter()
+                .any(|carried| Path::new(path).starts_with(carried))
+        };
+        self.files.retain(|file| !replaced(&file.path));
+        self.links.retain(|link| !replaced(link));
+        self.dirs.retain(|dir| !replaced(dir));
+        self.preserved = carried;
+    }
+
+    pub fn path(app_root: &Path, version: &str) -> PathBuf {
+        app_root
+            .join(RECEIPTS_SUBPATH)
+            .join(format!("{}.json", version))
+    }
+
+    pub fn load(path: &Path) -> Result<Receipt, Error> {
+        let receipt = fs::read(path)?;
+        serde_json::from_slice(&receipt).map_err(|error| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid receipt {:?}: {}", path, error),
+            )
+        })
+    }
+
+    /// Every receipt under `app_root`, one per installed version
+    pub fn load_all(app_root: &Path) -> Result<Vec<(PathBuf, Receipt)>, Error> {
+        let read_dir = match fs::read_dir(app_root.join(RECEIPTS_SUBPATH)) {
+            Ok(read_dir) => read_dir,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error),
+        };
+        let mut receipts = vec![];
+        for entry in read_dir {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                let receipt = Receipt::load(&path)?;
+                receipts.push((path, receipt));
+            }
+        }
+        Ok(receipts)
+    }
+
+    pub fn save(&self, app_root: &Path) -> Result<(), Error> {
+        let path = Receipt::path(app_root, &self.version);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Removes what the install created. Files changed since then are kept,
+    /// directories only go once they are empty. Preserved paths are moved under
+    /// `keep_preserved` when given, removed otherwise.
+    pub fn uninstall(&self, keep_preserved: Option<&Path>) -> Result<UninstallReport, Error> {
+        let mut report = UninstallReport::default();
+
+        for file in &self.files {
+            let path = self.install_dir.join(&file.path);
+            if !path.is_file() {
+                report.missing.push(path);
+                continue;
+            }
+            let unchanged = path.metadata()?.len() == file.size
+                && onefile::hash_file(&path, &mut NoProgress)? == file.sha256;
+            if unchanged {
+                fs::remove_file(&path)?;
+                report.removed_files += 1;
+            } else {
+                report.modified.push(path);
+            }
+        }
+
+        for link in &self.links {
+            let path = self.install_dir.join(link);
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.file_type().is_symlink() => remove_link(&path)?,
+                _ => report.missing.push(path),
+            }
+        }
+
+        for preserved in &self.preserved {
+            let path = self.install_dir.join(preserved);
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    report.missing.push(path);
+                    continue;
+                }
+            };
+            match keep_preserved {
+                Some(keep_dir) => {
+                    let kept = keep_dir.join(preserved);
+                    remove_any(&kept)?;
+                    fs::create_dir_all(kept.parent().unwrap_or(keep_dir))?;
+                    fs::rename(&path, &kept)?;
+                    report.kept.push(kept);
+                }
+                None => {
+                    if metadata.is_dir() {
+                        fs::remove_dir_all(&path)?;
+                    } else {
+                        remove_link(&path)?;
+                    }
+                    report.removed_files += 1;
+                }
+            }
+            // Parents the payload did not have were created by the carry over
+            for parent in path.ancestors().skip(1) {
+                if parent == self.install_dir || !remove_dir_if_empty(parent)? {
+                    break;
+                }
+            }
+        }
+
+        for path in &self.shortcuts {
+            if path.is_file() {
+                fs::remove_file(path)?;
+            } else {
+                report.missing.push(path.clone());
+            }
+        }
+
+        // Deepest first, so parents are empty by the time they are reached
+        let mut dirs = self.dirs.iter().collect::<Vec<_>>();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.matches('/').count()));
+        for dir in dirs {
+            remove_dir_if_empty(&self.install_dir.join(dir))?;
+        }
+        remove_dir_if_empty(&self.install_dir)?;
+
+        Ok(report)
+    }
+}
+
+/// Directory symlinks are directories to Windows
+fn remove_link(path: &Path) -> Result<(), Error> {
+    fs::remove_file(path).or_else(|_| fs::remove_dir(path))
+}
+
+fn remove_any(path: &Path) -> Result<(), Error> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => remove_link(path),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// `false` when the directory is kept because something is left in it
+pub fn remove_dir_if_empty(path: &Path) -> Result<bool, Error> {
+    match fs::read_dir(path) {
+        Ok(mut read_dir) => {
+            if read_dir.next().is_some() {
+                return Ok(false);
+            }
+            fs::remove_dir(path)?;
+            Ok(true)
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(true),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, kind: EntryKind, size: u64) -> EntryInfo {
+        EntryInfo {
+            path: path.to_string(),
+            kind,
+            link_target: None,
+            size,
+            compressed_size: None,
+            mode: None,
+            mtime: None,
+            crc32: None,
+        }
+    }
+
+    /// Installed into `dir`, with `config.json` and `data/cache` carried over
+    fn install_with_preserved(dir: &Path) -> Receipt {
+        fs::create_dir_all(dir.join("plugins")).unwrap();
+        fs::write(dir.join("app.exe"), "app").unwrap();
+        fs::write(dir.join("config.json"), "{}").unwrap();
+        let entries = [
+            entry("app.exe", EntryKind::File, 3),
+            entry("config.json", EntryKind::File, 2),
+            entry("plugins/", EntryKind::Dir, 0),
+        ];
+        let mut receipt = Receipt::new("app", "1.0.0", dir, &entries, dir).unwrap();
+
+        // Carried over after hashing, with content the payload never had
+        fs::write(dir.join("config.json"), "{\"theme\":\"dark\"}").unwrap();
+        fs::create_dir_all(dir.join("data/cache")).unwrap();
+        fs::write(dir.join("data/cache/index"), "index").unwrap();
+        receipt.set_preserved(vec!["config.json".to_string(), "data/cache".to_string()]);
+        assert_eq!(receipt.files.len(), 1);
+        receipt
+    }
+
+    #[test]
+    fn uninstall_keeps_preserved_paths_as_user_data() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("1.0.0");
+        let keep_dir = root.path().join("kept");
+        let receipt = install_with_preserved(&dir);
+
+        let report = receipt.uninstall(Some(&keep_dir)).unwrap();
+        assert!(report.modified.is_empty());
+        assert!(report.missing.is_empty());
+        assert_eq!(report.removed_files, 1);
+        assert_eq!(
+            report.kept,
+            [keep_dir.join("config.json"), keep_dir.join("data/cache")]
+        );
+        assert_eq!(
+            fs::read_to_string(keep_dir.join("config.json")).unwrap(),
+            "{\"theme\":\"dark\"}"
+        );
+        assert!(keep_dir.join("data/cache/index").is_file());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn purging_uninstall_removes_preserved_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("1.0.0");
+        let receipt = install_with_preserved(&dir);
+
+        let report = receipt.uninstall(None).unwrap();
+        assert!(report.kept.is_empty());
+        assert_eq!(report.removed_files, 3);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn modified_files_are_kept() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("1.0.0");
+        let receipt = install_with_preserved(&dir);
+        fs::write(dir.join("app.exe"), "patched").unwrap();
+
+        let report = receipt.uninstall(None).unwrap();
+        assert_eq!(report.modified, [dir.join("app.exe")]);
+        assert_eq!(fs::read_to_string(dir.join("app.exe")).unwrap(), "patched");
+    }
+
+    #[test]
+    fn keeps_shortcut_definitions_for_rollback() {
+        let root = tempfile::tempdir().unwrap();
+        let mut receipt = Receipt::new("app", "1.0.0", root.path(), &[], root.path()).unwrap();
+        receipt.shortcut_definitions = vec![Definition {
+            name: "App".to_string(),
+            target: "bin/app.exe".to_string(),
+            arguments: vec!["--safe".to_string()],
+            location: Default::default(),
+        }];
+        receipt.save(root.path()).unwrap();
+
+        let loaded = Receipt::load(&Receipt::path(root.path(), "1.0.0")).unwrap();
+        assert_eq!(loaded.shortcut_definitions[0].target, "bin/app.exe");
+        assert_eq!(loaded.shortcut_definitions[0].arguments, ["--safe"]);
+
+        // Written before definitions were recorded
+        let older = r#"{"app_id":"app","version":"0.9.0","install_dir":"/apps/app","files":[]}"#;
+        fs::write(Receipt::path(root.path(), "0.9.0"), older).unwrap();
+        let loaded = Receipt::load(&Receipt::path(root.path(), "0.9.0")).unwrap();
+        assert!(loaded.shortcut_definitions.is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    io::Error,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long killed processes get to disappear
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: u32,
+    pub exe: PathBuf,
+}
+
+#[derive(Debug, Default)]
+pub struct CloseReport {
+    /// Exited when asked to
+    pub closed: Vec<Process>,
+    pub killed: Vec<Process>,
+    pub still_running: Vec<Process>,
+}
+
+/// Running processes whose executable lives under `dir`, this process excluded
+pub fn find(dir: &Path) -> Result<Vec<Process>, Error> {
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(_) => return Ok(vec![]),
+    };
+    let this_pid = std::process::id();
+    Ok(processes()?
+        .into_iter()
+        .filter(|process| process.pid != this_pid && process.exe.starts_with(&dir))
+        .collect())
+}
+
+/// Asks every process running from `dir` to exit and waits up to `timeout` for them.
+/// With `force`, those still running afterwards are killed.
+pub fn close(dir: &Path, timeout: Duration, force: bool) -> Result<CloseReport, Error> {
+    let mut report = CloseReport::default();
+    let running = find(dir)?;
+    if running.is_empty() {
+        return Ok(report);
+    }
+
+    running.iter().for_each(request_exit);
+    let still_running = wait_for_exit(dir, &running, timeout)?;
+    report.closed = running
+        .into_iter()
+        .filter(|process| !contains(&still_running, process))
+        .collect();
+    if still_running.is_empty() || !force {
+        report.still_running = still_running;
+        return Ok(report);
+    }
+
+    for process in &still_running {
+        kill(process)?;
+    }
+    report.still_running = wait_for_exit(dir, &still_running, KILL_TIMEOUT)?;
+    report.killed = still_running
+        .into_iter()
+        .filter(|process| !contains(&report.still_running, process))
+        .collect();
+    Ok(report)
+}
+
+/// Those of `processes` still running from `dir` once `timeout` is over, or none
+fn wait_for_exit(
+    dir: &Path,
+    processes: &[Process],
+    timeout: Duration,
+) -> Result<Vec<Process>, Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let still_running = find(dir)?
+            .into_iter()
+            .filter(|process| contains(processes, process))
+            .collect::<Vec<_>>();
+        if still_running.is_empty() || Instant::now() >= deadline {
+            return Ok(still_running);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn contains(processes: &[Process], process: &Process) -> bool {
+    processes.iter().any(|other| other.pid == process.pid)
+}
+
+#[cfg(target_os = "linux")]
+fn processes() -> Result<Vec<Process>, Error> {
+    let mut processes = vec![];
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_str().and_then(|pid| pid.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // Processes of other users cannot be read, they cannot run our install either
+        let exe = match std::fs::read_link(entry.path().join("exe")) {
+            Ok(exe) => exe,
+            Err(_) => continue,
+        };
+        // The kernel marks executables replaced or removed since the process started
+        let exe = match exe.to_str().and_then(|exe| exe.strip_suffix(" (deleted)")) {
+            Some(exe) => PathBuf::from(exe),
+            None => exe,
+        };
+        processes.push(Process { pid, exe });
+    }
+    Ok(processes)
+}
+
+/// Without /proc there is no cheap way to map processes to executables
+#[cfg(all(unix, not(target_os = "linux")))]
+fn processes() -> Result<Vec<Process>, Error> {
+    Err(Error::new(
+        std::io::ErrorKind::Unsupported,
+        "listing processes by executable is only supported on Linux and Windows",
+    ))
+}
+
+#[cfg(unix)]
+fn request_exit(process: &Process) {
+    unsafe {
+        libc::kill(process.pid as libc::pid_t, libc::SIGTERM);
+    }
+}
+
+#[cfg(unix)]
+fn kill(process: &Process) -> Result<(), Error> {
+    if unsafe { libc::kill(process.pid as libc::pid_t, libc::SIGKILL) } == 0 {
+        return Ok(());
+    }
+    match Error::last_os_error() {
+        // Exited meanwhile
+        error if error.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+        error => Err(error),
+    }
+}
+
+#[cfg(windows)]
+fn processes() -> Result<Vec<Process>, Error> {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+    use windows::{
+        core::PWSTR,
+        Win32::{
+            Foundation::CloseHandle,
+            System::{
+                Diagnostics::ToolHelp::{
+                    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+                    TH32CS_SNAPPROCESS,
+                },
+                Threading::{
+                    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+                    PROCESS_QUERY_LIMITED_INFORMATION,
+                },
+            },
+        },
+    };
+
+    let mut processes = vec![];
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)?;
+        let mut entry = PROCESSENTRY32W {
+            dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+        let mut more = Process32FirstW(snapshot, &mut entry).as_bool();
+        while more {
+            let pid = entry.th32ProcessID;
+            // Processes of other users and system processes cannot be opened, skip them
+            if let Ok(handle) = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) {
+                let mut exe = [0u16; 1024];
+                let mut size = exe.len() as u32;
+                if QueryFullProcessImageNameW(
+                    handle,
+                    PROCESS_NAME_WIN32,
+                    PWSTR(exe.as_mut_ptr()),
+                    &mut size,
+                )
+                .as_bool()
+                {
+                    let exe = PathBuf::from(OsString::from_wide(&exe[..size as usize]));
+                    processes.push(Process { pid, exe });
+                }
+                CloseHandle(handle);
+            }
+            more = Process32NextW(snapshot, &mut entry).as_bool();
+        }
+        CloseHandle(snapshot);
+    }
+    Ok(processes)
+}
+
+/// Posts WM_CLOSE to the process' top level windows, as clicking their close button does.
+/// Processes without windows only go with `kill`.
+#[cfg(windows)]
+fn request_exit(process: &Process) {
+    use windows::Win32::{
+        Foundation::{BOOL, HWND, LPARAM, WPARAM},
+        UI::WindowsAndMessaging::{EnumWindows, GetWindowThreadProcessId, PostMessageW, WM_CLOSE},
+    };
+
+    unsafe extern "system" fn close_window(window: HWND, pid: LPARAM) -> BOOL {
+        let mut window_pid = 0;
+        GetWindowThreadProcessId(window, &mut window_pid);
+        if window_pid as isize == pid.0 {
+            PostMessageW(window, WM_CLOSE, WPARAM(0), LPARAM(0));
+        }
+        true.into()
+    }
+
+    unsafe {
+        EnumWindows(Some(close_window), LPARAM(process.pid as isize));
+    }
+}
+
+#[cfg(windows)]
+fn kill(process: &Process) -> Result<(), Error> {
+    use windows::Win32::{
+        Foundation::{CloseHandle, ERROR_INVALID_PARAMETER},
+        System::Threading::{OpenProcess, TerminateProcess, PROCESS_TERMINATE},
+    };
+
+    unsafe {
+        let handle = match OpenProcess(PROCESS_TERMINATE, false, process.pid) {
+            Ok(handle) => handle,
+            Err(_) => {
+                let error = Error::last_os_error();
+                // No such process, it exited meanwhile
+                if error.raw_os_error() == Some(ERROR_INVALID_PARAMETER.0 as i32) {
+                    return Ok(());
+                }
+                return Err(error);
+            }
+        };
+        let terminated = TerminateProcess(handle, 1).as_bool();
+        let error = Error::last_os_error();
+        CloseHandle(handle);
+        if terminated {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use serde::{Deserialize, Serialize};
+use sfx_zip::manifest::{Shortcut, ShortcutLocation};
+use std::{
+    fs,
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+/// A manifest shortcut without its spans, kept in the receipt to recreate it on rollback
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Definition {
+    pub name: String,
+    /// Relative to the app dir
+    pub target: String,
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    #[serde(default)]
+    pub location: ShortcutLocation,
+}
+
+impl From<&Shortcut> for Definition {
+    fn from(shortcut: &Shortcut) -> Definition {
+        Definition {
+            name: shortcut.name.get_ref().clone(),
+            target: shortcut.target.get_ref().clone(),
+            arguments: shortcut.arguments.clone(),
+            location: shortcut.location,
+        }
+    }
+}
+
+/// What a shortcut file points to and how it looks
+struct Link<'a> {
+    target: PathBuf,
+    arguments: &'a [String],
+    description: Option<&'a str>,
+    icon: Option<&'a Path>,
+}
+
+/// Creates `shortcut` of the app installed in `app_dir`, replacing an existing one.
+/// Returns the shortcut file, for the receipt.
+pub fn create(
+    app_id: &str,
+    shortcut: &Definition,
+    app_dir: &Path,
+    description: Option<&str>,
+    icon: Option<&Path>,
+) -> Result<PathBuf, Error> {
+    let name = &shortcut.name;
+    let dir = location_dir(shortcut.location)?;
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(file_name(app_id, name, shortcut.location));
+    let link = Link {
+        target: app_dir.join(&shortcut.target),
+        arguments: &shortcut.arguments,
+        description,
+        icon,
+    };
+    write(&path, name, &link)?;
+    Ok(path)
+}
+
+#[cfg(windows)]
+fn location_dir(location: ShortcutLocation) -> Result<PathBuf, Error> {
+    use crate::install_root::{process_env, required_env_path};
+
+    match location {
+        ShortcutLocation::StartMenu => Ok(required_env_path(&process_env, "APPDATA")?
+            .join(r"Microsoft\Windows\Start Menu\Programs")),
+        ShortcutLocation::Desktop => {
+            Ok(required_env_path(&process_env, "USERPROFILE")?.join("Desktop"))
+        }
+    }
+}
+
+#[cfg(windows)]
+fn file_name(_app_id: &str, name: &str, _location: ShortcutLocation) -> String {
+    format!("{}.lnk", name)
+}
+
+#[cfg(windows)]
+fn write(path: &Path, _name: &str, link: &Link) -> Result<(), Error> {
+    use std::{ffi::OsStr, os::windows::ffi::OsStrExt};
+    use windows::{
+        core::{IUnknown, Interface, PCWSTR},
+        Win32::{
+            System::Com::{
+                CoCreateInstance, CoInitializeEx, IPersistFile, CLSCTX_INPROC_SERVER,
+                COINIT_APARTMENTTHREADED,
+            },
+            UI::Shell::{IShellLinkW, ShellLink},
+        },
+    };
+
+    fn wide(text: impl AsRef<OsStr>) -> Vec<u16> {
+        text.as_ref().encode_wide().chain([0]).collect()
+    }
+
+    let arguments = link
+        .arguments
+        .iter()
+        .map(|argument| {
+            if argument.is_empty() || argument.contains([' ', '\t', '"']) {
+                format!("\"{}\"", argument.replace('"', "\\\""))
+            } else {
+                argument.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    unsafe {
+        // Fails when the thread already runs another COM mode, COM is usable anyway
+        let _ = CoInitializeEx(std::ptr::null(), COINIT_APARTMENTTHREADED);
+        let shell_link: IShellLinkW =
+            CoCreateInstance(&ShellLink, None::<&IUnknown>, CLSCTX_INPROC_SERVER)?;
+        shell_link.SetPath(PCWSTR(wide(&link.target).as_ptr()))?;
+        shell_link.SetArguments(PCWSTR(wide(&arguments).as_ptr()))?;
+        if let Some(working_dir) = link.target.parent() {
+            shell_link.SetWorkingDirectory(PCWSTR(wide(working_dir).as_ptr()))?;
+        }
+        if let Some(description) = link.description {
+            shell_link.SetDescription(PCWSTR(wide(description).as_ptr()))?;
+        }
+        if let Some(icon) = link.icon {
+            shell_link.SetIconLocation(PCWSTR(wide(icon).as_ptr()), 0)?;
+        }
+        let file: IPersistFile = shell_link.cast()?;
+        file.Save(PCWSTR(wide(path).as_ptr()), true)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn location_dir(location: ShortcutLocation) -> Result<PathBuf, Error> {
+    use crate::install_root::{data_home, env_path, home, process_env};
+
+    match location {
+        ShortcutLocation::StartMenu => Ok(data_home(&process_env)?.join("applications")),
+        ShortcutLocation::Desktop => match env_path(&process_env, "XDG_DESKTOP_DIR") {
+            Some(desktop) => Ok(desktop),
+            None => Ok(home(&process_env)?.join("Desktop")),
+        },
+    }
+}
+
+/// Menu entries are named by desktop file id, reverse-DNS like app ids,
+/// desktop files by what the user sees
+#[cfg(not(any(windows, target_os = "macos")))]
+fn file_name(app_id: &str, name: &str, location: ShortcutLocation) -> String {
+    match location {
+        ShortcutLocation::StartMenu => format!("{}.{}.desktop", app_id, name.replace(' ', "-")),
+        ShortcutLocation::Desktop => format!("{}.desktop", name),
+    }
+}
+
+/// A desktop entry, see the freedesktop.org Desktop Entry Specification
+#[cfg(not(any(windows, target_os = "macos")))]
+fn write(path: &Path, name: &str, link: &Link) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut exec = vec![quote_exec_argument(&link.target.to_string_lossy())];
+    exec.extend(
+        link.arguments
+            .iter()
+            .map(|argument| quote_exec_argument(argument)),
+    );
+
+    let mut entry = format!(
+        "[Desktop Entry]\nType=Application\nName={}\nExec={}\nTerminal=false\n",
+        escape_value(name),
+        exec.join(" ")
+    );
+    if let Some(working_dir) = link.target.parent() {
+        entry += &format!("Path={}\n", escape_value(&working_dir.to_string_lossy()));
+    }
+    if let Some(description) = link.description {
+        entry += &format!("Comment={}\n", escape_value(description));
+    }
+    if let Some(icon) = link.icon {
+        entry += &format!("Icon={}\n", escape_value(&icon.to_string_lossy()));
+    }
+
+    fs::write(path, entry)?;
+    // Desktops only launch entries on the desktop that are executable
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+/// Quoted as the `Exec` key wants it, before the escaping every string value gets
+#[cfg(not(any(windows, target_os = "macos")))]
+fn quote_exec_argument(argument: &str) -> String {
+    let mut quoted = String::from("\"");
+    for character in argument.chars() {
+        match character {
+            '"' | '`' | '$' | '\\' => {
+                quoted.push('\\');
+                quoted.push(character);
+            }
+            // Field codes start with %, a literal one is doubled
+            '%' => quoted.push_str("%%"),
+            _ => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+    escape_value(&quoted)
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn escape_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+#[cfg(target_os = "macos")]
+fn unsupported() -> Error {
+    Error::new(
+        std::io::ErrorKind::Unsupported,
+        "shortcuts are not supported on macOS",
+    )
+}
+
+#[cfg(target_os = "macos")]
+fn location_dir(_location: ShortcutLocation) -> Result<PathBuf, Error> {
+    Err(unsupported())
+}
+
+#[cfg(target_os = "macos")]
+fn file_name(_app_id: &str, name: &str, _location: ShortcutLocation) -> String {
+    name.to_string()
+}
+
+#[cfg(target_os = "macos")]
+fn write(_path: &Path, _name: &str, _link: &Link) -> Result<(), Error> {
+    Err(unsupported())
+}
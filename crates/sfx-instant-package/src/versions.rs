@@ -0,0 +1,185 @@
+use sfx_zip::metadata::check_semver;
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+pub const VERSIONS_SUBPATH: &str = "versions";
+/// Pointer file holding the version in use, a file rather than a symlink
+/// since Windows users cannot create symlinks by default
+pub const CURRENT_FILE_NAME: &str = "current";
+/// Installed versions, oldest first, one per line
+pub const HISTORY_FILE_NAME: &str = "history";
+
+/// Side-by-side installs of one app: `<app_root>/versions/<version>/`
+/// and `<app_root>/current` naming the one in use
+pub struct Versions {
+    app_root: PathBuf,
+}
+
+impl Versions {
+    pub fn new<P: Into<PathBuf>>(app_root: P) -> Versions {
+        Versions {
+            app_root: app_root.into(),
+        }
+    }
+
+    pub fn dir(&self, version: &str) -> PathBuf {
+        self.app_root.join(VERSIONS_SUBPATH).join(version)
+    }
+
+    pub fn current(&self) -> Result<Option<String>, Error> {
+        match fs::read_to_string(self.app_root.join(CURRENT_FILE_NAME)) {
+            Ok(current) => Ok(Some(current.trim().to_string())),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn set_current(&self, version: &str) -> Result<(), Error> {
+        write_replacing(&self.app_root.join(CURRENT_FILE_NAME), version)
+    }
+
+    /// Installed versions, oldest first; versions whose directory is gone are left out
+    pub fn history(&self) -> Result<Vec<String>, Error> {
+        let history = match fs::read_to_string(self.app_root.join(HISTORY_FILE_NAME)) {
+            Ok(history) => history,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+        Ok(history
+            .lines()
+            .map(str::trim)
+            .filter(|version| !version.is_empty() && self.dir(version).is_dir())
+            .map(String::from)
+            .collect())
+    }
+
+    /// Marks `version` as the newest install and points `current` to it
+    pub fn record_install(&self, version: &str) -> Result<(), Error> {
+        let mut history = self.history()?;
+        history.retain(|installed| installed != version);
+        history.push(version.to_string());
+        write_replacing(
+            &self.app_root.join(HISTORY_FILE_NAME),
+            &(history.join("\n") + "\n"),
+        )?;
+        self.set_current(version)
+    }
+
+    /// Removes the oldest versions until at most `keep` besides the current one remain.
+    /// Returns the removed versions.
+    pub fn prune(&self, keep: usize) -> Result<Vec<String>, Error> {
+        let current = self.current()?;
+        let history = self.history()?;
+        let previous = history
+            .iter()
+            .filter(|version| Some(*version) != current.as_ref())
+            .collect::<Vec<_>>();
+
+        let mut removed = vec![];
+        for version in &previous[..previous.len().saturating_sub(keep)] {
+            fs::remove_dir_all(self.dir(version))?;
+            removed.push(version.to_string());
+        }
+
+        let history = history
+            .into_iter()
+            .filter(|version| !removed.contains(version))
+            .collect::<Vec<_>>();
+        write_replacing(
+            &self.app_root.join(HISTORY_FILE_NAME),
+            &(history.join("\n") + "\n"),
+        )?;
+        Ok(removed)
+    }
+
+    /// Removes `current`, `history` and the versions dir once uninstall emptied it
+    pub fn forget(&self) -> Result<(), Error> {
+        for file_name in [CURRENT_FILE_NAME, HISTORY_FILE_NAME] {
+            match fs::remove_file(self.app_root.join(file_name)) {
+                Err(error) if error.kind() != ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+        }
+        match fs::remove_dir(self.app_root.join(VERSIONS_SUBPATH)) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(Error::new(
+                error.kind(),
+                format!("versions were left in place: {}", error),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// `to`, or the newest version installed before the current one
+    pub fn rollback_target(&self, to: Option<&str>) -> Result<String, Error> {
+        let history = self.history()?;
+        if let Some(to) = to {
+            return match history.iter().find(|version| *version == to) {
+                Some(version) => Ok(version.clone()),
+                None => Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "version {:?} is not installed, installed: {}",
+                        to,
+                        history.join(", ")
+                    ),
+                )),
+            };
+        }
+
+        let current = self.current()?;
+        let older = match history
+            .iter()
+            .position(|version| Some(version) == current.as_ref())
+        {
+            Some(index) => &history[..index],
+            None => &history[..],
+        };
+        older.last().cloned().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "no version older than the current one is installed",
+            )
+        })
+    }
+}
+
+/// Written next to `path` and renamed over it, so readers never see a partial file
+fn write_replacing(path: &Path, content: &str) -> Result<(), Error> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, path)
+}
+
+/// The semantic version the archive was built with. Versions name directories,
+/// which semver's `[0-9A-Za-z.+-]` keeps safe.
+pub fn validate_version(version: &str) -> Result<(), Error> {
+    check_semver(version).map(|_| ()).map_err(|message| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("version {:?} {}", version, message),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_semantic() {
+        for version in ["1.2.0", "2.0.0-rc.1", "1.0.0+build.5"] {
+            assert!(validate_version(version).is_ok(), "{}", version);
+        }
+        for version in ["", "1.2", "v1.2.0", "../1.0.0", "1.0.0/x", "1.0.0_beta"] {
+            assert!(validate_version(version).is_err(), "{}", version);
+        }
+    }
+}
@@ -1,9 +1,11 @@
 use crate::common::get_custom_data_key;
+use crate::icon::Icon;
+use crate::version_info::VersionInfo;
 
 use super::common::{get_index_key, BLOCKSIZE, RES_NAME_COUNT, RES_TYPE};
 use std::ffi::{c_void, CString};
 use std::fs;
-use std::io::{BufReader, Read};
+use std::io::{self, BufReader, Read};
 use windows::core::PCSTR;
 use windows::Win32::Foundation::HANDLE;
 use windows::Win32::System::LibraryLoader;
@@ -19,7 +21,7 @@ pub fn with_resource_update_handle<'a, T>(
     let handle = unsafe {
         LibraryLoader::BeginUpdateResourceA(
             PCSTR::from_raw(cstr_file_path_ptr),
-            &flag_remove_existing_resource,
+            flag_remove_existing_resource,
         )
         .unwrap()
     };
@@ -33,40 +35,92 @@ pub fn embed_custom_string(
     key: impl Into<String>,
     value: impl Into<String>,
 ) -> bool {
+    embed_custom_data(handle, key, value.into().as_bytes())
+}
+
+/// Binary counterpart of `embed_custom_string`, read back with `extractor::read_custom_data`
+pub fn embed_custom_data(handle: &HANDLE, key: impl Into<String>, value: &[u8]) -> bool {
     let res_type_cstr: CString = CString::new(RES_TYPE).unwrap();
     let res_name: CString = CString::new(get_custom_data_key(&key.into())).unwrap();
 
-    let value = value.into();
-    let value_as_buffer = value.as_bytes();
-    let value_as_buffer_ptr = value_as_buffer as *const _ as *const c_void;
+    update_resource(
+        handle,
+        PCSTR::from_raw(res_type_cstr.as_bytes_with_nul().as_ptr()),
+        PCSTR::from_raw(res_name.as_bytes_with_nul().as_ptr()),
+        value,
+    )
+}
+
+/// Standard `RT_VERSION` resource, shown by Explorer in the exe's properties
+pub fn embed_version_info(handle: &HANDLE, version_info: &VersionInfo) -> bool {
+    update_resource(
+        handle,
+        int_resource(RT_VERSION),
+        int_resource(1),
+        &version_info.to_bytes(),
+    )
+}
+
+/// Icon of the exe itself, as shown by Explorer and the taskbar
+pub fn embed_icon(handle: &HANDLE, icon: &Icon) -> bool {
+    let mut embedded = update_resource(
+        handle,
+        int_resource(RT_GROUP_ICON),
+        int_resource(1),
+        &icon.group,
+    );
+    for (id, image) in &icon.images {
+        embedded &= update_resource(handle, int_resource(RT_ICON), int_resource(*id), image);
+    }
+    embedded
+}
+
+const RT_ICON: u16 = 3;
+const RT_GROUP_ICON: u16 = 14;
+const RT_VERSION: u16 = 16;
 
+/// `MAKEINTRESOURCE`: numeric ids travel in the pointer itself
+fn int_resource(id: u16) -> PCSTR {
+    PCSTR::from_raw(id as usize as *const u8)
+}
+
+fn update_resource(handle: &HANDLE, res_type: PCSTR, res_name: PCSTR, data: &[u8]) -> bool {
     unsafe {
         LibraryLoader::UpdateResourceA(
             *handle,
-            PCSTR::from_raw(res_type_cstr.as_bytes_with_nul().as_ptr()),
-            PCSTR::from_raw(res_name.as_bytes_with_nul().as_ptr()),
+            res_type,
+            res_name,
             0x0409,
-            value_as_buffer_ptr,
-            (std::mem::size_of::<u8>() * value_as_buffer.len())
-                .try_into()
-                .unwrap(),
+            data as *const _ as *const c_void,
+            data.len().try_into().unwrap(),
         )
         .as_bool()
     }
 }
 
-pub fn embed_binary_as_archive(handle: &HANDLE, file_path: &std::path::Path) -> Result<(), ()> {
+pub fn embed_binary_as_archive(handle: &HANDLE, file_path: &std::path::Path) -> io::Result<()> {
+    embed_binary_as_archive_with_progress(handle, file_path, &mut |_, _| {})
+}
+
+/// `progress` is called with (bytes embedded, total bytes) after every block
+pub fn embed_binary_as_archive_with_progress(
+    handle: &HANDLE,
+    file_path: &std::path::Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> io::Result<()> {
     let res_type_cstr: CString = CString::new(RES_TYPE).unwrap();
-    let file = fs::File::open(file_path).unwrap();
+    let file = fs::File::open(file_path)?;
+    let total_size = file.metadata()?.len();
     let mut buf_reader = BufReader::new(file);
     let mut index = 0_u32;
+    let mut embedded_size = 0_u64;
 
     loop {
         let mut take_handle = (&mut buf_reader).take(BLOCKSIZE as u64);
         let mut buffer = [0; BLOCKSIZE];
         let read_res = take_handle.read(&mut buffer);
 
-        let actual_read_size = read_res.unwrap();
+        let actual_read_size = read_res?;
         if actual_read_size == 0 {
             break;
         }
@@ -83,25 +137,24 @@ pub fn embed_binary_as_archive(handle: &HANDLE, file_path: &std::path::Path) ->
                 PCSTR::from_raw(res_name.as_bytes_with_nul().as_ptr()),
                 0x0409,
                 final_buffer_ptr,
-                (std::mem::size_of::<u8>() * final_buffer.len())
-                    .try_into()
-                    .unwrap(),
+                std::mem::size_of_val(final_buffer).try_into().unwrap(),
             )
             .as_bool()
         };
 
         if !success {
-            return Err(());
+            return Err(io::Error::last_os_error());
         }
 
+        embedded_size += actual_read_size as u64;
+        progress(embedded_size, total_size);
         index += 1;
     }
     let block_count = index;
-    embed_block_count(handle, &block_count).unwrap();
-    Ok(())
+    embed_block_count(handle, &block_count)
 }
 
-fn embed_block_count(handle: &HANDLE, block_count: &u32) -> Result<(), ()> {
+fn embed_block_count(handle: &HANDLE, block_count: &u32) -> io::Result<()> {
     let res_type_cstr: CString = CString::new(RES_TYPE).unwrap();
     let res_name_count_cstr: CString = CString::new(RES_NAME_COUNT).unwrap();
 
@@ -116,7 +169,7 @@ fn embed_block_count(handle: &HANDLE, block_count: &u32) -> Result<(), ()> {
             PCSTR::from_raw(res_name_count_cstr.as_bytes_with_nul().as_ptr()),
             0x0409,
             block_count_in_string_buffer_ptr,
-            (std::mem::size_of::<u8>() * block_count_in_string_buffer.len())
+            std::mem::size_of_val(block_count_in_string_buffer)
                 .try_into()
                 .unwrap(),
         );
@@ -126,6 +179,6 @@ fn embed_block_count(handle: &HANDLE, block_count: &u32) -> Result<(), ()> {
     if success {
         Ok(())
     } else {
-        Err(())
+        Err(io::Error::last_os_error())
     }
 }
@@ -1,10 +1,11 @@
 use crate::common::get_custom_data_key;
 
-use super::common::{get_index_key, RES_NAME_COUNT, RES_TYPE};
+use super::common::{get_index_key, BLOCKSIZE, RES_NAME_COUNT, RES_TYPE};
 use std::ffi::CString;
 use std::fs;
 use std::io::BufWriter;
 use std::io::Write;
+use std::io::{self, Read, Seek, SeekFrom};
 use windows::core::PCSTR;
 use windows::Win32::Foundation::HINSTANCE;
 use windows::Win32::System::LibraryLoader;
@@ -15,19 +16,114 @@ fn read_block_count() -> Result<u32, ()> {
     Ok(data)
 }
 
-pub fn extract_binary(file_path: &std::path::Path) -> Result<(), ()> {
+pub fn extract_binary(file_path: &std::path::Path) -> io::Result<()> {
     let block_count = read_block_count().unwrap();
     let file = fs::File::options()
         .create_new(true)
         .write(true)
-        .open(file_path)
-        .unwrap();
+        .open(file_path)?;
     let mut buf_writer = BufWriter::new(file);
     for block_index in 0..block_count {
         let chunk = read_resource_as_vec_u8(RES_TYPE, get_index_key(&block_index)).unwrap();
-        buf_writer.write_all(chunk.as_slice()).unwrap();
+        buf_writer.write_all(chunk.as_slice())?;
+    }
+    buf_writer.flush()
+}
+
+/// Reads the embedded binary block by block, straight from the exe's resources.
+/// Unlike `extract_binary`, nothing is written to disk.
+pub struct PayloadReader {
+    block_count: u32,
+    len: u64,
+    position: u64,
+    block: Option<(u32, Vec<u8>)>,
+}
+
+impl PayloadReader {
+    pub fn new() -> io::Result<PayloadReader> {
+        let block_count = read_block_count().map_err(|_| {
+            io::Error::new(io::ErrorKind::NotFound, "payload block count is missing")
+        })?;
+        let len = match block_count {
+            0 => 0,
+            _ => {
+                let last_block = read_block(block_count - 1)?;
+                (block_count as u64 - 1) * BLOCKSIZE as u64 + last_block.len() as u64
+            }
+        };
+        Ok(PayloadReader {
+            block_count,
+            len,
+            position: 0,
+            block: None,
+        })
+    }
+
+    /// Size of the embedded binary in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+fn read_block(block_index: u32) -> io::Result<Vec<u8>> {
+    read_resource_as_vec_u8(RES_TYPE, get_index_key(&block_index)).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("payload block {} is missing", block_index),
+        )
+    })
+}
+
+impl Read for PayloadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_index = (self.position / BLOCKSIZE as u64) as u32;
+        let offset = (self.position % BLOCKSIZE as u64) as usize;
+        if block_index >= self.block_count {
+            return Ok(0);
+        }
+
+        let block = match &self.block {
+            Some((index, block)) if *index == block_index => block,
+            _ => {
+                let block = read_block(block_index)?;
+                &self.block.insert((block_index, block)).1
+            }
+        };
+
+        let available = &block[offset.min(block.len())..];
+        let read_size = available.len().min(buf.len());
+        buf[..read_size].copy_from_slice(&available[..read_size]);
+        self.position += read_size as u64;
+        Ok(read_size)
+    }
+}
+
+impl Seek for PayloadReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
     }
-    Ok(())
 }
 
 pub fn read_resource_as_vec_u8(
@@ -75,7 +171,7 @@ pub fn read_resource_as_vec_u8(
 }
 
 pub fn read_custom_string(key: impl Into<String>) -> Option<String> {
-    let vec_opt = read_resource_as_vec_u8(RES_TYPE, get_custom_data_key(&key.into()));
+    let vec_opt = read_custom_data(key);
     match vec_opt {
         Some(vec) => {
             let data = String::from_utf8(vec).unwrap();
@@ -84,3 +180,8 @@ pub fn read_custom_string(key: impl Into<String>) -> Option<String> {
         None => None,
     }
 }
+
+/// Data embedded with `embedder::embed_custom_data`
+pub fn read_custom_data(key: impl Into<String>) -> Option<Vec<u8>> {
+    read_resource_as_vec_u8(RES_TYPE, get_custom_data_key(&key.into()))
+}
@@ -0,0 +1,56 @@
+/// An .ico file split the way exe resources store it: one `RT_ICON` per image
+/// and an `RT_GROUP_ICON` directory pointing to them by id
+#[derive(Debug, Clone)]
+pub struct Icon {
+    pub group: Vec<u8>,
+    /// Resource id and image data
+    pub images: Vec<(u16, Vec<u8>)>,
+}
+
+const ICON_DIR_SIZE: usize = 6;
+const ICON_DIR_ENTRY_SIZE: usize = 16;
+
+impl Icon {
+    pub fn parse(ico: &[u8]) -> Result<Icon, String> {
+        let word = |offset: usize| u16::from_le_bytes([ico[offset], ico[offset + 1]]);
+        let dword = |offset: usize| {
+            u32::from_le_bytes([
+                ico[offset],
+                ico[offset + 1],
+                ico[offset + 2],
+                ico[offset + 3],
+            ])
+        };
+
+        if ico.len() < ICON_DIR_SIZE || word(0) != 0 || word(2) != 1 {
+            return Err(String::from("not an .ico file"));
+        }
+        let count = word(4) as usize;
+        if count == 0 || ico.len() < ICON_DIR_SIZE + count * ICON_DIR_ENTRY_SIZE {
+            return Err(String::from("truncated .ico file"));
+        }
+
+        let mut group = ico[..ICON_DIR_SIZE].to_vec();
+        let mut images = vec![];
+        for index in 0..count {
+            let entry = ICON_DIR_SIZE + index * ICON_DIR_ENTRY_SIZE;
+            let size = dword(entry + 8) as usize;
+            let offset = dword(entry + 12) as usize;
+            let image = match offset
+                .checked_add(size)
+                .and_then(|end| ico.get(offset..end))
+            {
+                Some(image) => image.to_vec(),
+                None => return Err(format!("image {} lies outside the .ico file", index + 1)),
+            };
+            let id = index as u16 + 1;
+
+            // Same entry with the file offset replaced by the resource id
+            group.extend(&ico[entry..entry + 12]);
+            group.extend(id.to_le_bytes());
+            images.push((id, image));
+        }
+
+        Ok(Icon { group, images })
+    }
+}
@@ -2,3 +2,5 @@ pub use windows;
 pub mod common;
 pub mod embedder;
 pub mod extractor;
+pub mod icon;
+pub mod version_info;
@@ -1,6 +1,8 @@
 pub mod common;
 pub mod embedder;
 pub mod extractor;
+pub mod icon;
+pub mod version_info;
 use std::fs;
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -0,0 +1,111 @@
+/// Language and code page of the string table: US English, UTF-16
+const LANGUAGE: u16 = 0x0409;
+const CODE_PAGE: u16 = 1200;
+
+/// `VS_VERSIONINFO` resource, what Explorer shows in the Details tab of an exe
+#[derive(Debug, Clone, Default)]
+pub struct VersionInfo {
+    pub file_version: [u16; 4],
+    pub product_version: [u16; 4],
+    /// `StringFileInfo` entries such as `ProductName` or `CompanyName`
+    pub strings: Vec<(String, String)>,
+}
+
+impl VersionInfo {
+    /// Resource data as `UpdateResource` takes it for `RT_VERSION`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut fixed_file_info = vec![];
+        for value in [
+            0xFEEF04BD, // signature
+            0x00010000, // structure version
+            join(self.file_version[0], self.file_version[1]),
+            join(self.file_version[2], self.file_version[3]),
+            join(self.product_version[0], self.product_version[1]),
+            join(self.product_version[2], self.product_version[3]),
+            0x3F,       // file flags mask
+            0,          // file flags
+            0x00040004, // VOS_NT_WINDOWS32
+            1,          // VFT_APP
+            0,          // file subtype
+            0,          // file date, most significant
+            0,          // file date, least significant
+        ] {
+            fixed_file_info.extend(value.to_le_bytes());
+        }
+
+        let strings = self
+            .strings
+            .iter()
+            .map(|(key, value)| {
+                let value = wide(value);
+                // In characters, terminating nul included
+                block(key, 1, (value.len() / 2) as u16, &value, &[])
+            })
+            .collect::<Vec<_>>();
+        let string_table = block(
+            &format!("{:04X}{:04X}", LANGUAGE, CODE_PAGE),
+            1,
+            0,
+            &[],
+            &strings,
+        );
+        let string_file_info = block("StringFileInfo", 1, 0, &[], &[string_table]);
+
+        let translation = ((CODE_PAGE as u32) << 16 | LANGUAGE as u32).to_le_bytes();
+        let var = block("Translation", 0, 4, &translation, &[]);
+        let var_file_info = block("VarFileInfo", 1, 0, &[], &[var]);
+
+        block(
+            "VS_VERSION_INFO",
+            0,
+            fixed_file_info.len() as u16,
+            &fixed_file_info,
+            &[string_file_info, var_file_info],
+        )
+    }
+}
+
+fn join(high: u16, low: u16) -> u32 {
+    (high as u32) << 16 | low as u32
+}
+
+/// One node of the resource tree: length, value length, type, key, value, children,
+/// each of key, value and children aligned on 32 bits
+fn block(
+    key: &str,
+    value_type: u16,
+    value_length: u16,
+    value: &[u8],
+    children: &[Vec<u8>],
+) -> Vec<u8> {
+    // Length is patched in once known
+    let mut bytes = vec![0, 0];
+    bytes.extend(value_length.to_le_bytes());
+    bytes.extend(value_type.to_le_bytes());
+    bytes.extend(wide(key));
+    if !value.is_empty() {
+        align(&mut bytes);
+        bytes.extend(value);
+    }
+    for child in children {
+        align(&mut bytes);
+        bytes.extend(child);
+    }
+    let length = bytes.len() as u16;
+    bytes[..2].copy_from_slice(&length.to_le_bytes());
+    bytes
+}
+
+/// UTF-16LE, nul terminated
+fn wide(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .chain([0])
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+fn align(bytes: &mut Vec<u8>) {
+    while !bytes.len().is_multiple_of(4) {
+        bytes.push(0);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::archive_format::ArchiveFormat;
+use crate::compression::{CompressionOptions, Method};
+use crate::zip_fns::ArchiveOptions;
+use structopt::StructOpt;
+
+/// Archive flags shared by every CLI that builds a payload
+#[derive(Debug, StructOpt, Clone)]
+pub struct ArchiveArgs {
+    /// Only archive files matching these globs (relative to source)
+    #[structopt(long)]
+    pub include: Vec<String>,
+    /// Skip entries matching these gitignore-style patterns, in addition to source/.sfxignore
+    #[structopt(long)]
+    pub exclude: Vec<String>,
+    /// Sort entries and fix timestamps (honours SOURCE_DATE_EPOCH) for byte-identical output
+    #[structopt(long)]
+    pub reproducible: bool,
+    /// Payload format: zip, tar.zst or tar.xz
+    #[structopt(long, default_value = "zip")]
+    pub format: ArchiveFormat,
+    /// Zip method for compressible files: store, deflate, bzip2 or zstd
+    #[structopt(long, default_value = "deflate")]
+    pub compression_method: Method,
+    /// Level for the compression method, or for the whole stream of tar payloads
+    #[structopt(long, allow_hyphen_values = true)]
+    pub compression_level: Option<i32>,
+    /// Additional extensions to store without compression, e.g. `--store-extension dat`
+    #[structopt(long)]
+    pub store_extension: Vec<String>,
+    /// Compress every file with the chosen method, even already-compressed ones
+    #[structopt(long)]
+    pub no_store_rules: bool,
+    /// Compression worker threads, defaults to the number of cores
+    #[structopt(short = "j", long)]
+    pub jobs: Option<usize>,
+}
+
+impl ArchiveArgs {
+    pub fn archive_options(&self) -> ArchiveOptions {
+        let mut compression = CompressionOptions {
+            method: self.compression_method,
+            level: self.compression_level,
+            ..Default::default()
+        };
+        if self.no_store_rules {
+            compression.stored_extensions.clear();
+            compression.entropy_probe = false;
+        }
+        compression.stored_extensions.extend(
+            self.store_extension
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_ascii_lowercase()),
+        );
+
+        ArchiveOptions {
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            reproducible: self.reproducible,
+            compression,
+            jobs: self.jobs,
+        }
+    }
+}
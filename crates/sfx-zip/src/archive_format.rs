@@ -0,0 +1,342 @@
+use crate::extract::{self, ExtractError, ExtractOptions, ExtractSummary};
+use crate::listing::EntryInfo;
+use crate::progress::{NoProgress, Progress};
+use crate::source_filter::SkipSummary;
+use crate::sources::SourceMapping;
+use crate::tar_fns;
+use crate::zip_fns::{self, ArchiveOptions};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufReader, BufWriter, Error, ErrorKind, Read, Seek},
+    ops::RangeInclusive,
+    path::Path,
+    str::FromStr,
+};
+
+/// Custom string in the payload metadata recording which `ArchiveFormat` the payload uses.
+/// Payloads without it predate the format layer and are zip.
+pub const FORMAT_KEY: &str = "format";
+
+const ZSTD_LEVEL: i32 = 19;
+const ZSTD_LEVEL_RANGE: RangeInclusive<i32> = 1..=22;
+const XZ_PRESET: i32 = 9;
+const XZ_PRESET_RANGE: RangeInclusive<i32> = 0..=9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArchiveFormat {
+    #[default]
+    Zip,
+    TarZstd,
+    TarXz,
+}
+
+/// Turns source directories and files into a payload file and back
+pub trait PayloadFormat {
+    fn archive(
+        &self,
+        sources: &[SourceMapping],
+        destination: &Path,
+        options: &ArchiveOptions,
+        progress: &mut dyn Progress,
+    ) -> std::io::Result<SkipSummary>;
+
+    /// Visits every entry in archive order with a reader over its uncompressed content
+    fn for_each_entry(
+        &self,
+        reader: &mut dyn ReadSeek,
+        visit: &mut dyn FnMut(EntryInfo, &mut dyn Read) -> std::io::Result<()>,
+    ) -> std::io::Result<()>;
+
+    fn list_from_reader(&self, reader: &mut dyn ReadSeek) -> std::io::Result<Vec<EntryInfo>>;
+
+    /// Uncompressed size of all entries, if the format can tell without decompressing
+    fn total_size(&self, _reader: &mut dyn ReadSeek) -> std::io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+impl ArchiveFormat {
+    pub const ALL: [ArchiveFormat; 3] = [
+        ArchiveFormat::Zip,
+        ArchiveFormat::TarZstd,
+        ArchiveFormat::TarXz,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarZstd => "tar.zst",
+            ArchiveFormat::TarXz => "tar.xz",
+        }
+    }
+
+    /// Levels of the single compressed stream of tar formats, `None` for zip where
+    /// each entry's compression method decides
+    pub fn stream_level_range(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            ArchiveFormat::Zip => None,
+            ArchiveFormat::TarZstd => Some(ZSTD_LEVEL_RANGE),
+            ArchiveFormat::TarXz => Some(XZ_PRESET_RANGE),
+        }
+    }
+
+    /// Format recorded under `FORMAT_KEY`, `None` meaning a payload without the key
+    pub fn from_metadata(value: Option<String>) -> Result<ArchiveFormat, Error> {
+        match value {
+            Some(value) => value.parse(),
+            None => Ok(ArchiveFormat::Zip),
+        }
+    }
+
+    pub fn implementation(&self) -> &'static dyn PayloadFormat {
+        match self {
+            ArchiveFormat::Zip => &ZipFormat,
+            ArchiveFormat::TarZstd => &TarZstdFormat,
+            ArchiveFormat::TarXz => &TarXzFormat,
+        }
+    }
+
+    pub fn archive<P: AsRef<Path>>(
+        &self,
+        source: P,
+        destination: P,
+        options: &ArchiveOptions,
+        progress: &mut dyn Progress,
+    ) -> std::io::Result<SkipSummary> {
+        self.archive_sources(
+            &[SourceMapping::root(source.as_ref())],
+            destination.as_ref(),
+            options,
+            progress,
+        )
+    }
+
+    /// Combines several sources into one payload, see `sources::collect_sources`
+    pub fn archive_sources(
+        &self,
+        sources: &[SourceMapping],
+        destination: &Path,
+        options: &ArchiveOptions,
+        progress: &mut dyn Progress,
+    ) -> std::io::Result<SkipSummary> {
+        self.implementation()
+            .archive(sources, destination, options, progress)
+    }
+
+    pub fn extract<P: AsRef<Path>>(&self, payload: P, destination: P) -> std::io::Result<()> {
+        let file = BufReader::new(File::open(payload)?);
+        self.extract_from_reader(file, destination)
+    }
+
+    pub fn list_from_reader<R: Read + Seek>(
+        &self,
+        mut reader: R,
+    ) -> std::io::Result<Vec<EntryInfo>> {
+        self.implementation().list_from_reader(&mut reader)
+    }
+
+    /// Extracts without a payload file on disk, e.g. from `sfx_ll::extractor::PayloadReader`
+    pub fn extract_from_reader<R, P>(&self, reader: R, destination: P) -> std::io::Result<()>
+    where
+        R: Read + Seek,
+        P: AsRef<Path>,
+    {
+        self.extract_with_options(
+            reader,
+            destination,
+            &ExtractOptions::default(),
+            &mut NoProgress,
+        )?;
+        Ok(())
+    }
+
+    /// Extracts the entries selected by `options`, see `extract::ExtractOptions`
+    pub fn extract_with_options<R, P>(
+        &self,
+        mut reader: R,
+        destination: P,
+        options: &ExtractOptions,
+        progress: &mut dyn Progress,
+    ) -> Result<ExtractSummary, ExtractError>
+    where
+        R: Read + Seek,
+        P: AsRef<Path>,
+    {
+        extract::extract_entries(
+            self.implementation(),
+            &mut reader,
+            destination.as_ref(),
+            options,
+            progress,
+        )
+    }
+}
+
+impl Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ArchiveFormat::ALL
+            .into_iter()
+            .find(|format| format.as_str() == s)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "unknown archive format {:?}, expected one of: {}",
+                        s,
+                        ArchiveFormat::ALL.map(|format| format.as_str()).join(", ")
+                    ),
+                )
+            })
+    }
+}
+
+/// By name, as in manifests
+impl Serialize for ArchiveFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ArchiveFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Tar payloads are compressed as one stream, so only the global level applies to them
+fn tar_level(
+    options: &ArchiveOptions,
+    default: i32,
+    range: RangeInclusive<i32>,
+) -> Result<i32, Error> {
+    match options.compression.level {
+        None => Ok(default),
+        Some(level) if range.contains(&level) => Ok(level),
+        Some(level) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "compression level {} is out of range, expected {}..={}",
+                level,
+                range.start(),
+                range.end()
+            ),
+        )),
+    }
+}
+
+pub struct ZipFormat;
+
+impl PayloadFormat for ZipFormat {
+    fn archive(
+        &self,
+        sources: &[SourceMapping],
+        destination: &Path,
+        options: &ArchiveOptions,
+        progress: &mut dyn Progress,
+    ) -> std::io::Result<SkipSummary> {
+        zip_fns::archive_sources(sources, destination, options, progress)
+    }
+
+    fn for_each_entry(
+        &self,
+        reader: &mut dyn ReadSeek,
+        visit: &mut dyn FnMut(EntryInfo, &mut dyn Read) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        zip_fns::for_each_entry(reader, visit)
+    }
+
+    fn list_from_reader(&self, reader: &mut dyn ReadSeek) -> std::io::Result<Vec<EntryInfo>> {
+        zip_fns::list_from_reader(reader)
+    }
+
+    fn total_size(&self, reader: &mut dyn ReadSeek) -> std::io::Result<Option<u64>> {
+        let entries = zip_fns::list_from_reader(reader)?;
+        Ok(Some(entries.iter().map(|entry| entry.size).sum()))
+    }
+}
+
+pub struct TarZstdFormat;
+
+impl PayloadFormat for TarZstdFormat {
+    fn archive(
+        &self,
+        sources: &[SourceMapping],
+        destination: &Path,
+        options: &ArchiveOptions,
+        progress: &mut dyn Progress,
+    ) -> std::io::Result<SkipSummary> {
+        let level = tar_level(options, ZSTD_LEVEL, ZSTD_LEVEL_RANGE)?;
+        let file = BufWriter::new(File::create(destination)?);
+        let mut encoder = zstd::Encoder::new(file, level)?;
+        // Always multithreaded: zstd output then no longer depends on the worker count
+        encoder.multithread(options.jobs() as u32)?;
+        let skip_summary = tar_fns::archive_sources(sources, &mut encoder, options, progress)?;
+        encoder.finish()?;
+        Ok(skip_summary)
+    }
+
+    fn for_each_entry(
+        &self,
+        reader: &mut dyn ReadSeek,
+        visit: &mut dyn FnMut(EntryInfo, &mut dyn Read) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        tar_fns::for_each_entry(zstd::Decoder::new(reader)?, visit)
+    }
+
+    fn list_from_reader(&self, reader: &mut dyn ReadSeek) -> std::io::Result<Vec<EntryInfo>> {
+        tar_fns::list(zstd::Decoder::new(reader)?)
+    }
+}
+
+pub struct TarXzFormat;
+
+impl PayloadFormat for TarXzFormat {
+    fn archive(
+        &self,
+        sources: &[SourceMapping],
+        destination: &Path,
+        options: &ArchiveOptions,
+        progress: &mut dyn Progress,
+    ) -> std::io::Result<SkipSummary> {
+        let preset = tar_level(options, XZ_PRESET, XZ_PRESET_RANGE)?;
+        let file = BufWriter::new(File::create(destination)?);
+        // Always multithreaded: block layout then no longer depends on the thread count
+        let stream = xz2::stream::MtStreamBuilder::new()
+            .threads(options.jobs() as u32)
+            .preset(preset as u32)
+            .check(xz2::stream::Check::Crc64)
+            .encoder()?;
+        let mut encoder = xz2::write::XzEncoder::new_stream(file, stream);
+        let skip_summary = tar_fns::archive_sources(sources, &mut encoder, options, progress)?;
+        encoder.finish()?;
+        Ok(skip_summary)
+    }
+
+    fn for_each_entry(
+        &self,
+        reader: &mut dyn ReadSeek,
+        visit: &mut dyn FnMut(EntryInfo, &mut dyn Read) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        tar_fns::for_each_entry(xz2::read::XzDecoder::new(reader), visit)
+    }
+
+    fn list_from_reader(&self, reader: &mut dyn ReadSeek) -> std::io::Result<Vec<EntryInfo>> {
+        tar_fns::list(xz2::read::XzDecoder::new(reader))
+    }
+}
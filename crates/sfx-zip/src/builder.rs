@@ -0,0 +1,522 @@
+use crate::archive_format::{ArchiveFormat, FORMAT_KEY};
+use crate::entrypoint::ENTRYPOINT_KEY;
+use crate::extract::ExtractError;
+use crate::manifest::{Manifest, MANIFEST_KEY};
+use crate::metadata::{AppMetadata, ICON_KEY, METADATA_KEY};
+use crate::onefile::{self, ONEFILE_KEY, PAYLOAD_HASH_KEY};
+use crate::progress::{self, Progress};
+use crate::source_filter::SkipSummary;
+use crate::sources::SourceMapping;
+use crate::zip_fns::{archive_key, ArchiveOptions};
+use sfx_ll::{embedder, icon::Icon};
+use std::{
+    fmt::Display,
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum SfxZipError {
+    /// Every problem found before anything was written
+    Invalid(Vec<Error>),
+    Io(Error),
+    Extract(ExtractError),
+}
+
+impl Display for SfxZipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SfxZipError::Invalid(errors) => {
+                write!(f, "{} error occurred", errors.len())?;
+                errors
+                    .iter()
+                    .try_for_each(|error| write!(f, "\nerror: {}", error))
+            }
+            SfxZipError::Io(error) => write!(f, "{}", error),
+            SfxZipError::Extract(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SfxZipError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SfxZipError::Invalid(_) => None,
+            SfxZipError::Io(error) => Some(error),
+            SfxZipError::Extract(error) => Some(error),
+        }
+    }
+}
+
+impl From<Error> for SfxZipError {
+    fn from(error: Error) -> Self {
+        SfxZipError::Io(error)
+    }
+}
+
+impl From<ExtractError> for SfxZipError {
+    fn from(error: ExtractError) -> Self {
+        SfxZipError::Extract(error)
+    }
+}
+
+impl From<SfxZipError> for Error {
+    fn from(error: SfxZipError) -> Self {
+        match error {
+            SfxZipError::Io(error) => error,
+            SfxZipError::Extract(error) => error.into(),
+            SfxZipError::Invalid(_) => Error::new(ErrorKind::InvalidInput, error.to_string()),
+        }
+    }
+}
+
+/// Builds a self-extracting exe: archives the sources, then embeds the payload into a copy of the stub.
+///
+/// ```no_run
+/// # use sfx_zip::{progress::NoProgress, SfxZipBuilder};
+/// SfxZipBuilder::new("dist", "installer.exe")
+///     .stub("target/release/sfx-zip.exe")
+///     .entry_point("app.exe")
+///     .build(&mut NoProgress)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SfxZipBuilder {
+    sources: Vec<SourceMapping>,
+    destination: PathBuf,
+    stub: Option<PathBuf>,
+    temp_payload: Option<PathBuf>,
+    entry_point: Option<PathBuf>,
+    onefile: bool,
+    force: bool,
+    format: ArchiveFormat,
+    options: ArchiveOptions,
+    custom_strings: Vec<(String, String)>,
+    metadata: Option<AppMetadata>,
+    icon: Option<PathBuf>,
+}
+
+impl SfxZipBuilder {
+    pub fn new<S: Into<PathBuf>, D: Into<PathBuf>>(source: S, destination: D) -> SfxZipBuilder {
+        SfxZipBuilder::from_sources(vec![SourceMapping::root(source)], destination)
+    }
+
+    /// Several directories and files combined into one payload, see `SourceMapping`
+    pub fn from_sources<D: Into<PathBuf>>(
+        sources: Vec<SourceMapping>,
+        destination: D,
+    ) -> SfxZipBuilder {
+        SfxZipBuilder {
+            sources,
+            destination: destination.into(),
+            stub: None,
+            temp_payload: None,
+            entry_point: None,
+            onefile: false,
+            force: false,
+            format: ArchiveFormat::default(),
+            options: ArchiveOptions::default(),
+            custom_strings: vec![],
+            metadata: None,
+            icon: None,
+        }
+    }
+
+    /// Source, entrypoint, format, archive options, app metadata and icon from `manifest`,
+    /// which is embedded in normalized form under `MANIFEST_KEY`
+    pub fn from_manifest<D: Into<PathBuf>>(manifest: &Manifest, destination: D) -> SfxZipBuilder {
+        let mut builder = SfxZipBuilder::from_sources(manifest.source_mappings(), destination)
+            .format(manifest.format())
+            .options(manifest.archive_options())
+            .metadata(manifest.metadata())
+            .custom_string(MANIFEST_KEY, manifest.normalized());
+        builder.entry_point = manifest.entry_point();
+        builder.icon = manifest.icon();
+        builder
+    }
+
+    pub fn add_source(mut self, source: SourceMapping) -> SfxZipBuilder {
+        self.sources.push(source);
+        self
+    }
+
+    /// The sfx-zip exe the payload is embedded into, the running exe by default.
+    /// Build scripts and other tools have to set it.
+    pub fn stub<P: Into<PathBuf>>(mut self, stub: P) -> SfxZipBuilder {
+        self.stub = Some(stub.into());
+        self
+    }
+
+    /// Where the payload is written before embedding, next to the destination by default.
+    /// It is removed once the exe is built.
+    pub fn temp_payload<P: Into<PathBuf>>(mut self, temp_payload: P) -> SfxZipBuilder {
+        self.temp_payload = Some(temp_payload.into());
+        self
+    }
+
+    /// Path inside the archive, e.g. `bin/app.exe`
+    pub fn entry_point<P: Into<PathBuf>>(mut self, entry_point: P) -> SfxZipBuilder {
+        self.entry_point = Some(entry_point.into());
+        self
+    }
+
+    /// See `onefile`, requires an entry point
+    pub fn onefile(mut self, onefile: bool) -> SfxZipBuilder {
+        self.onefile = onefile;
+        self
+    }
+
+    /// Replace an existing destination and temp payload
+    pub fn force(mut self, force: bool) -> SfxZipBuilder {
+        self.force = force;
+        self
+    }
+
+    pub fn format(mut self, format: ArchiveFormat) -> SfxZipBuilder {
+        self.format = format;
+        self
+    }
+
+    pub fn options(mut self, options: ArchiveOptions) -> SfxZipBuilder {
+        self.options = options;
+        self
+    }
+
+    /// Extra metadata embedded next to the payload, read back with
+    /// `sfx_ll::extractor::read_custom_string`
+    pub fn custom_string<K: Into<String>, V: Into<String>>(
+        mut self,
+        key: K,
+        value: V,
+    ) -> SfxZipBuilder {
+        self.custom_strings.push((key.into(), value.into()));
+        self
+    }
+
+    /// Embedded under `METADATA_KEY` and as the exe's version resource
+    pub fn metadata(mut self, metadata: AppMetadata) -> SfxZipBuilder {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// An .ico file, embedded under `ICON_KEY` and as the exe's own icon
+    pub fn icon<P: Into<PathBuf>>(mut self, icon: P) -> SfxZipBuilder {
+        self.icon = Some(icon.into());
+        self
+    }
+
+    fn temp_payload_path(&self) -> PathBuf {
+        match &self.temp_payload {
+            Some(temp_payload) => temp_payload.clone(),
+            None => self.destination.with_extension("sfx-payload"),
+        }
+    }
+
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors: Vec<Error> = vec![];
+
+        if self.sources.is_empty() {
+            errors.push(Error::new(
+                ErrorKind::InvalidInput,
+                "at least one source is required.",
+            ));
+        }
+        for source in &self.sources {
+            errors.extend(source.validate());
+        }
+
+        if self.temp_payload_path().exists() && !self.force {
+            errors.push(Error::other(
+                "temp_payload already exists. temp_payload cannot exist.",
+            ));
+        }
+
+        if self.destination.exists() && !self.force {
+            errors.push(Error::other(
+                "destination already exists. destination cannot exist.",
+            ));
+        }
+
+        if let Some(stub) = &self.stub {
+            if !stub.is_file() {
+                errors.push(Error::new(ErrorKind::NotFound, "stub is not a file."));
+            }
+        }
+
+        if let Some(entry_point) = &self.entry_point {
+            if entry_point.is_absolute() {
+                errors.push(Error::other("entry_point cannot be absolute path."));
+            }
+
+            let exists = self.sources.iter().any(|source| {
+                source
+                    .resolve(entry_point)
+                    .is_some_and(|absolute_entry_point| absolute_entry_point.exists())
+            });
+
+            if !exists {
+                errors.push(Error::other("entry_point does not exist"));
+            }
+        }
+
+        if self.onefile && self.entry_point.is_none() {
+            errors.push(Error::new(
+                ErrorKind::InvalidInput,
+                "onefile requires entry_point.",
+            ));
+        }
+
+        if let Err(error) = self.options.compression.validate(self.format) {
+            errors.push(error);
+        }
+
+        if let Some(metadata) = &self.metadata {
+            errors.extend(metadata.validate());
+        }
+
+        if let Some(icon) = &self.icon {
+            if let Err(error) = read_icon(icon) {
+                errors.push(error);
+            }
+        }
+
+        errors
+    }
+
+    pub fn build(&self, progress: &mut dyn Progress) -> Result<SkipSummary, SfxZipError> {
+        let errors = self.validate();
+        if !errors.is_empty() {
+            return Err(SfxZipError::Invalid(errors));
+        }
+
+        let temp_payload = self.temp_payload_path();
+        ensure_gone(&self.destination)?;
+        ensure_gone(&temp_payload)?;
+
+        // Cleaning up after a failure is best effort, the failure is what gets reported
+        let result = self.build_with_payload(&temp_payload, progress);
+        let removed = ensure_gone(&temp_payload);
+        match result {
+            Ok(skip_summary) => removed.map(|_| skip_summary).map_err(SfxZipError::from),
+            Err(error) => {
+                let _ = ensure_gone(&self.destination);
+                Err(error)
+            }
+        }
+    }
+
+    fn build_with_payload(
+        &self,
+        temp_payload: &Path,
+        progress: &mut dyn Progress,
+    ) -> Result<SkipSummary, SfxZipError> {
+        // Make payload file
+        let skip_summary =
+            self.format
+                .archive_sources(&self.sources, temp_payload, &self.options, progress)?;
+
+        let payload_hash = if self.onefile {
+            Some(onefile::hash_file(temp_payload, progress)?)
+        } else {
+            None
+        };
+
+        let icon = match &self.icon {
+            Some(icon) => Some(read_icon(icon)?),
+            None => None,
+        };
+
+        let stub = match &self.stub {
+            Some(stub) => stub.clone(),
+            None => std::env::current_exe()?,
+        };
+        fs::copy(stub, &self.destination)?;
+
+        let embedded = embedder::with_resource_update_handle(
+            self.destination.as_path(),
+            Box::new(|handle| {
+                let mut embedded = embedder::embed_binary_as_archive_with_progress(
+                    handle,
+                    temp_payload,
+                    &mut progress::embedding(progress),
+                )
+                .is_ok();
+
+                let mut embed = |key: &str, value: &str| {
+                    embedded &= embedder::embed_custom_string(handle, key, value);
+                };
+
+                embed(FORMAT_KEY, self.format.as_str());
+
+                if let Some(payload_hash) = &payload_hash {
+                    embed(ONEFILE_KEY, ONEFILE_KEY);
+                    embed(PAYLOAD_HASH_KEY, payload_hash);
+                }
+
+                if let Some(entry_point) = &self.entry_point {
+                    embed(ENTRYPOINT_KEY, &archive_key(entry_point));
+                }
+
+                for (key, value) in &self.custom_strings {
+                    embed(key, value);
+                }
+
+                if let Some(metadata) = &self.metadata {
+                    embed(METADATA_KEY, &metadata.to_json());
+                    embedded &= embedder::embed_version_info(handle, &metadata.version_info());
+                }
+
+                if let Some((ico, icon)) = &icon {
+                    embedded &= embedder::embed_custom_data(handle, ICON_KEY, ico);
+                    embedded &= embedder::embed_icon(handle, icon);
+                }
+
+                embedded
+            }),
+        );
+        if !embedded {
+            return Err(SfxZipError::Io(Error::other(format!(
+                "cannot embed the payload into {:?}",
+                self.destination
+            ))));
+        }
+
+        Ok(skip_summary)
+    }
+}
+
+/// The file as is, and split into exe resources
+fn read_icon(path: &Path) -> Result<(Vec<u8>, Icon), Error> {
+    let ico = fs::read(path).map_err(|error| {
+        Error::new(
+            error.kind(),
+            format!("cannot read icon {:?}: {}", path, error),
+        )
+    })?;
+    let icon = Icon::parse(&ico).map_err(|message| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("icon {:?}: {}", path, message),
+        )
+    })?;
+    Ok((ico, icon))
+}
+
+pub(crate) fn ensure_gone<P: AsRef<Path>>(filepath: P) -> Result<(), Error> {
+    let filepath = filepath.as_ref();
+
+    if !filepath.exists() {
+        return Ok(());
+    }
+
+    if filepath.is_dir() {
+        fs::remove_dir_all(filepath)
+    } else {
+        fs::remove_file(filepath)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(builder: &SfxZipBuilder) -> Vec<String> {
+        let errors = builder.validate();
+        errors.iter().map(|error| error.to_string()).collect()
+    }
+
+    #[test]
+    fn problems_are_reported_together_before_writing() {
+        let root = tempfile::tempdir().unwrap();
+        let destination = root.path().join("installer.exe");
+        let builder = SfxZipBuilder::from_sources(vec![], &destination)
+            .stub(root.path().join("missing-stub.exe"))
+            .entry_point(root.path().join("app.exe"))
+            .onefile(true);
+
+        let errors = invalid(&builder.clone().entry_point("app.exe"));
+        assert!(errors.contains(&"at least one source is required.".to_string()));
+        assert!(errors.contains(&"stub is not a file.".to_string()));
+        assert!(errors.contains(&"entry_point does not exist".to_string()));
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+
+        let errors = invalid(&builder.onefile(false));
+        assert!(errors.contains(&"entry_point cannot be absolute path.".to_string()));
+        assert!(!destination.exists());
+    }
+
+    #[test]
+    fn onefile_requires_an_entry_point() {
+        let root = tempfile::tempdir().unwrap();
+        let builder =
+            SfxZipBuilder::new(root.path(), root.path().join("installer.exe")).onefile(true);
+        assert_eq!(invalid(&builder), ["onefile requires entry_point."]);
+    }
+
+    #[test]
+    fn existing_outputs_need_force() {
+        let root = tempfile::tempdir().unwrap();
+        let destination = root.path().join("installer.exe");
+        let temp_payload = root.path().join("payload.zip");
+        fs::write(&destination, "old").unwrap();
+        fs::write(&temp_payload, "old").unwrap();
+        let builder =
+            SfxZipBuilder::new(root.path().join("dist"), &destination).temp_payload(&temp_payload);
+        fs::create_dir(root.path().join("dist")).unwrap();
+
+        assert_eq!(
+            invalid(&builder),
+            [
+                "temp_payload already exists. temp_payload cannot exist.",
+                "destination already exists. destination cannot exist.",
+            ]
+        );
+        assert!(builder.force(true).validate().is_empty());
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "old");
+    }
+
+    // Building embeds through the Windows resource API
+    #[cfg(windows)]
+    #[test]
+    fn failed_builds_clean_up_and_keep_their_error() {
+        use crate::progress::NoProgress;
+
+        let root = tempfile::tempdir().unwrap();
+        let dist = root.path().join("dist");
+        fs::create_dir(&dist).unwrap();
+        fs::write(dist.join("app.exe"), "app").unwrap();
+        let stub = root.path().join("stub.exe");
+        fs::write(&stub, "stub").unwrap();
+        // The stub cannot be copied into a missing directory
+        let destination = root.path().join("missing").join("installer.exe");
+        let temp_payload = root.path().join("payload.zip");
+
+        let builder = SfxZipBuilder::new(&dist, &destination)
+            .stub(&stub)
+            .temp_payload(&temp_payload);
+        match builder.clone().onefile(true).build(&mut NoProgress) {
+            Err(SfxZipError::Invalid(errors)) => assert_eq!(errors.len(), 1),
+            other => panic!("expected validation errors, got {:?}", other),
+        }
+        match builder.build(&mut NoProgress) {
+            Err(SfxZipError::Io(_)) => {}
+            other => panic!("expected the copy error, got {:?}", other),
+        }
+        assert!(!temp_payload.exists());
+        assert!(!destination.exists());
+    }
+
+    #[test]
+    fn invalid_display_counts_the_errors() {
+        let error = SfxZipError::Invalid(vec![
+            Error::other("first problem"),
+            Error::other("second problem"),
+        ]);
+        assert_eq!(
+            error.to_string(),
+            "2 error occurred\nerror: first problem\nerror: second problem"
+        );
+        assert_eq!(Error::from(error).kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,375 @@
+use crate::archive_format::ArchiveFormat;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt::Display,
+    io::{Error, ErrorKind},
+    ops::RangeInclusive,
+    path::Path,
+    str::FromStr,
+};
+use zip::CompressionMethod;
+
+/// Extensions whose content is already compressed; deflating them again only costs time
+pub const DEFAULT_STORED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "br", "bz2", "cab", "docx", "flac", "gif", "gz", "jar", "jpeg",
+    "jpg", "lz4", "m4a", "mkv", "mov", "mp3", "mp4", "msi", "nupkg", "ogg", "pptx", "png", "rar",
+    "tgz", "war", "webm", "webp", "whl", "woff", "woff2", "xlsx", "xz", "zip", "zst",
+];
+
+/// How many leading bytes of a file the entropy probe looks at
+pub const ENTROPY_SAMPLE_SIZE: usize = 64 * 1024;
+
+/// In bits per byte. Compressed and encrypted data sits close to the maximum of 8.
+pub const ENTROPY_THRESHOLD: f64 = 7.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Store,
+    Deflate,
+    Bzip2,
+    Zstd,
+}
+
+impl Method {
+    pub const ALL: [Method; 4] = [Method::Store, Method::Deflate, Method::Bzip2, Method::Zstd];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Store => "store",
+            Method::Deflate => "deflate",
+            Method::Bzip2 => "bzip2",
+            Method::Zstd => "zstd",
+        }
+    }
+
+    pub fn zip_method(&self) -> CompressionMethod {
+        match self {
+            Method::Store => CompressionMethod::Stored,
+            Method::Deflate => CompressionMethod::Deflated,
+            Method::Bzip2 => CompressionMethod::Bzip2,
+            Method::Zstd => CompressionMethod::Zstd,
+        }
+    }
+
+    /// Levels the zip crate accepts, `None` when the method takes no level
+    pub fn level_range(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            Method::Store => None,
+            Method::Deflate => Some(0..=9),
+            Method::Bzip2 => Some(1..=9),
+            Method::Zstd => Some(-7..=22),
+        }
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Method {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Method::ALL
+            .into_iter()
+            .find(|method| method.as_str() == s)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "unknown compression method {:?}, expected one of: {}",
+                        s,
+                        Method::ALL.map(|method| method.as_str()).join(", ")
+                    ),
+                )
+            })
+    }
+}
+
+/// By name, as in manifests
+impl Serialize for Method {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Method {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressionOptions {
+    /// Method for files that no store rule matches
+    pub method: Method,
+    /// Applies to `method`; stored files have no level. `None` picks the method's default.
+    pub level: Option<i32>,
+    /// Lowercase extensions without the dot, files with these are stored as is
+    pub stored_extensions: Vec<String>,
+    /// Store files whose leading bytes look incompressible
+    pub entropy_probe: bool,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        CompressionOptions {
+            method: Method::Deflate,
+            level: None,
+            stored_extensions: DEFAULT_STORED_EXTENSIONS
+                .iter()
+                .map(|extension| String::from(*extension))
+                .collect(),
+            entropy_probe: true,
+        }
+    }
+}
+
+impl CompressionOptions {
+    /// Checks `level` against the range of what `format` compresses with: each entry's
+    /// method for zip, the single stream for tar formats
+    pub fn validate(&self, format: ArchiveFormat) -> Result<(), Error> {
+        let level = match self.level {
+            Some(level) => level,
+            None => return Ok(()),
+        };
+        if let Some(range) = format.stream_level_range() {
+            return if range.contains(&level) {
+                Ok(())
+            } else {
+                Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "compression level {} is out of range for {}, expected {}..={}",
+                        level,
+                        format,
+                        range.start(),
+                        range.end()
+                    ),
+                ))
+            };
+        }
+        match self.method.level_range() {
+            Some(range) if range.contains(&level) => Ok(()),
+            Some(range) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "compression level {} is out of range for {}, expected {}..={}",
+                    level,
+                    self.method,
+                    range.start(),
+                    range.end()
+                ),
+            )),
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} does not take a compression level", self.method),
+            )),
+        }
+    }
+
+    /// Picks the method for one file given its path and content
+    pub fn method_for(&self, path: &Path, content: &[u8]) -> Method {
+        let has_stored_extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| {
+                let extension = extension.to_ascii_lowercase();
+                self.stored_extensions.contains(&extension)
+            })
+            .unwrap_or(false);
+
+        if has_stored_extension {
+            return Method::Store;
+        }
+
+        if self.entropy_probe {
+            let sample = &content[..content.len().min(ENTROPY_SAMPLE_SIZE)];
+            // Tiny files do not carry enough bytes for a meaningful estimate
+            if sample.len() >= 4096 && shannon_entropy(sample) > ENTROPY_THRESHOLD {
+                return Method::Store;
+            }
+        }
+
+        self.method
+    }
+
+    pub fn zip_options(&self, method: Method) -> zip::write::FileOptions {
+        let level = match method {
+            Method::Store => None,
+            _ => self.level,
+        };
+        zip::write::FileOptions::default()
+            .compression_method(method.zip_method())
+            .compression_level(level)
+    }
+}
+
+/// Shannon entropy of `bytes` in bits per byte, between 0 and 8
+pub fn shannon_entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0_usize; 256];
+    bytes.iter().for_each(|byte| counts[*byte as usize] += 1);
+    let total = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let probability = *count as f64 / total;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_level(method: Method, level: i32) -> CompressionOptions {
+        CompressionOptions {
+            method,
+            level: Some(level),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn zip_levels_follow_the_method() {
+        assert!(with_level(Method::Deflate, 9)
+            .validate(ArchiveFormat::Zip)
+            .is_ok());
+        assert!(with_level(Method::Deflate, 19)
+            .validate(ArchiveFormat::Zip)
+            .is_err());
+        assert!(with_level(Method::Zstd, 19)
+            .validate(ArchiveFormat::Zip)
+            .is_ok());
+        assert!(with_level(Method::Store, 1)
+            .validate(ArchiveFormat::Zip)
+            .is_err());
+    }
+
+    #[test]
+    fn tar_levels_follow_the_stream() {
+        // The default method is deflate, which tops out at 9
+        assert!(with_level(Method::Deflate, 19)
+            .validate(ArchiveFormat::TarZstd)
+            .is_ok());
+        assert!(with_level(Method::Deflate, 23)
+            .validate(ArchiveFormat::TarZstd)
+            .is_err());
+        assert!(with_level(Method::Deflate, 0)
+            .validate(ArchiveFormat::TarXz)
+            .is_ok());
+        assert!(with_level(Method::Store, 6)
+            .validate(ArchiveFormat::TarXz)
+            .is_ok());
+        assert!(with_level(Method::Deflate, 10)
+            .validate(ArchiveFormat::TarXz)
+            .is_err());
+    }
+
+    /// Bytes from a xorshift generator, as incompressible as random data
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn text(len: usize) -> Vec<u8> {
+        b"The quick brown fox jumps over the lazy dog.\n"
+            .iter()
+            .copied()
+            .cycle()
+            .take(len)
+            .collect()
+    }
+
+    #[test]
+    fn compressed_extensions_are_stored() {
+        let options = CompressionOptions::default();
+        for name in ["photo.jpg", "Photo.JPG", "lib/bundle.zip", "font.woff2"] {
+            assert_eq!(
+                options.method_for(Path::new(name), &text(10_000)),
+                Method::Store,
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            options.method_for(Path::new("zip"), &text(10_000)),
+            Method::Deflate
+        );
+
+        let options = CompressionOptions {
+            stored_extensions: vec![],
+            ..Default::default()
+        };
+        assert_eq!(
+            options.method_for(Path::new("photo.jpg"), &text(10_000)),
+            Method::Deflate
+        );
+    }
+
+    #[test]
+    fn high_entropy_content_is_stored() {
+        let options = CompressionOptions {
+            method: Method::Zstd,
+            ..Default::default()
+        };
+        assert!(shannon_entropy(&noise(ENTROPY_SAMPLE_SIZE)) > ENTROPY_THRESHOLD);
+        assert_eq!(
+            options.method_for(Path::new("data.bin"), &noise(100_000)),
+            Method::Store
+        );
+        // Too small to judge
+        assert_eq!(
+            options.method_for(Path::new("data.bin"), &noise(1000)),
+            Method::Zstd
+        );
+        // Only the leading sample is probed
+        let mut content = text(ENTROPY_SAMPLE_SIZE);
+        content.extend(noise(100_000));
+        assert_eq!(
+            options.method_for(Path::new("data.bin"), &content),
+            Method::Zstd
+        );
+
+        let options = CompressionOptions {
+            entropy_probe: false,
+            ..options
+        };
+        assert_eq!(
+            options.method_for(Path::new("data.bin"), &noise(100_000)),
+            Method::Zstd
+        );
+    }
+
+    #[test]
+    fn ordinary_text_uses_the_configured_method() {
+        for method in Method::ALL {
+            let options = CompressionOptions {
+                method,
+                ..Default::default()
+            };
+            assert_eq!(
+                options.method_for(Path::new("readme.txt"), &text(100_000)),
+                method
+            );
+            assert_eq!(options.method_for(Path::new("empty"), &[]), method);
+        }
+        assert!(shannon_entropy(&text(ENTROPY_SAMPLE_SIZE)) < 5.0);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Custom string holding the entrypoint, relative to the archive root and `/`-separated
+pub const ENTRYPOINT_KEY: &str = "entrypoint";
+
+/// Resolves the embedded entrypoint against the extraction destination.
+/// Fails when it does not exist or ends up outside `destination`, symlinks included.
+pub fn resolve<P: AsRef<Path>>(destination: P, entrypoint: &str) -> Result<PathBuf, Error> {
+    let destination = destination.as_ref().canonicalize()?;
+
+    let mut relative = PathBuf::new();
+    for part in entrypoint.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("entrypoint {:?} must not contain ..", entrypoint),
+                ))
+            }
+            _ if part.contains(':') => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("entrypoint {:?} must be relative", entrypoint),
+                ))
+            }
+            _ => relative.push(part),
+        }
+    }
+
+    let resolved = destination.join(relative).canonicalize().map_err(|error| {
+        Error::new(
+            error.kind(),
+            format!("entrypoint {:?} not found: {}", entrypoint, error),
+        )
+    })?;
+
+    if !resolved.starts_with(&destination) || !resolved.is_file() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "entrypoint {:?} resolves to {:?}, which is not a file inside {:?}",
+                entrypoint, resolved, destination
+            ),
+        ));
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(unix)]
+pub fn mark_executable<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = path.as_ref();
+    let mut permissions = path.metadata()?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    std::fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+pub fn mark_executable<P: AsRef<Path>>(_path: P) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Runs `entrypoint` to completion and returns its exit code.
+/// A child killed by a signal reports 1.
+pub fn run<P: AsRef<Path>>(entrypoint: P, args: &[String]) -> Result<i32, Error> {
+    let entrypoint = entrypoint.as_ref();
+    mark_executable(entrypoint)?;
+    let status = Command::new(entrypoint).args(args).status()?;
+    Ok(status.code().unwrap_or(1))
+}
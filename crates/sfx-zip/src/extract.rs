@@ -0,0 +1,1424 @@
+use crate::archive_format::{PayloadFormat, ReadSeek};
+use crate::listing::{EntryInfo, EntryKind};
+use crate::progress::{Phase, Progress, Tracker};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::{
+    collections::{hash_map, HashMap},
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Error, ErrorKind, Read, SeekFrom},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// What happens when an extracted file already exists in the destination.
+/// Files in the destination that the payload does not contain are always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    #[default]
+    Always,
+    Never,
+    /// Overwrite only when the entry's modification time is later than the existing file's
+    Newer,
+    /// Refuse to extract anything when any file already exists
+    Fail,
+}
+
+impl OverwritePolicy {
+    pub const ALL: [OverwritePolicy; 4] = [
+        OverwritePolicy::Always,
+        OverwritePolicy::Never,
+        OverwritePolicy::Newer,
+        OverwritePolicy::Fail,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OverwritePolicy::Always => "always",
+            OverwritePolicy::Never => "never",
+            OverwritePolicy::Newer => "newer",
+            OverwritePolicy::Fail => "fail",
+        }
+    }
+}
+
+impl Display for OverwritePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OverwritePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OverwritePolicy::ALL
+            .into_iter()
+            .find(|policy| policy.as_str() == s)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "unknown overwrite policy {:?}, expected one of: {}",
+                        s,
+                        OverwritePolicy::ALL
+                            .map(|policy| policy.as_str())
+                            .join(", ")
+                    ),
+                )
+            })
+    }
+}
+
+/// Defaults for `ExtractLimits`, far above any real payload
+pub const DEFAULT_MAX_TOTAL_SIZE: u64 = 64 * 1024 * 1024 * 1024;
+pub const DEFAULT_MAX_ENTRIES: usize = 1_000_000;
+
+/// Stops archives that expand far beyond their size.
+/// Sizes are counted as bytes are written, declared sizes are not trusted.
+#[derive(Debug, Clone, Copy)]
+pub struct ExtractLimits {
+    pub max_total_size: u64,
+    pub max_entries: usize,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        ExtractLimits {
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ExtractOptions {
+    /// Globs matched against the entry path inside the archive.
+    /// When given, only matching entries are extracted.
+    pub only: Vec<String>,
+    /// Leading path removed from every entry, e.g. `bin` extracts `bin/app.dll` as `app.dll`.
+    /// Entries outside of it are skipped.
+    pub strip_prefix: Option<String>,
+    pub overwrite: OverwritePolicy,
+    pub limits: ExtractLimits,
+}
+
+/// Why an extraction was refused. Nothing after the offending entry is written.
+#[derive(Debug)]
+pub enum ExtractError {
+    Io(io::Error),
+    /// Path with a `..` component
+    ParentTraversal {
+        path: String,
+    },
+    /// Path starting at a root, e.g. `/etc/passwd` or `\\server\share`
+    AbsolutePath {
+        path: String,
+    },
+    /// Path with a drive or stream prefix, e.g. `C:\Windows` or `file:stream`
+    DrivePrefix {
+        path: String,
+    },
+    /// Windows device name such as `CON` or `NUL.txt`
+    ReservedName {
+        path: String,
+    },
+    /// Symlink whose target is absolute or leaves the destination,
+    /// or an entry that would be written through such a link
+    SymlinkEscape {
+        path: String,
+        target: String,
+    },
+    /// Hardlinks, devices and fifos are never extracted
+    UnsupportedEntry {
+        path: String,
+        kind: EntryKind,
+    },
+    Duplicate {
+        path: String,
+    },
+    /// Two entries that are the same file on a case-insensitive file system
+    CaseCollision {
+        path: String,
+        existing: String,
+    },
+    TooManyEntries {
+        limit: usize,
+    },
+    TooLarge {
+        limit: u64,
+    },
+    /// Files that `OverwritePolicy::Fail` refused to replace
+    AlreadyExists {
+        paths: Vec<PathBuf>,
+    },
+    /// Destination entry of the wrong type, e.g. a directory where the payload has a file
+    Conflict {
+        path: PathBuf,
+    },
+}
+
+impl Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractError::Io(error) => write!(f, "{}", error),
+            ExtractError::ParentTraversal { path } => write!(f, "entry {:?} contains ..", path),
+            ExtractError::AbsolutePath { path } => write!(f, "entry {:?} is absolute", path),
+            ExtractError::DrivePrefix { path } => {
+                write!(f, "entry {:?} has a drive prefix", path)
+            }
+            ExtractError::ReservedName { path } => {
+                write!(f, "entry {:?} uses a reserved device name", path)
+            }
+            ExtractError::SymlinkEscape { path, target } => write!(
+                f,
+                "entry {:?} leaves the destination through symlink target {:?}",
+                path, target
+            ),
+            ExtractError::UnsupportedEntry { path, kind } => {
+                write!(f, "entry {:?} is an unsupported {:?} entry", path, kind)
+            }
+            ExtractError::Duplicate { path } => write!(f, "entry {:?} appears twice", path),
+            ExtractError::CaseCollision { path, existing } => write!(
+                f,
+                "entry {:?} collides with {:?} on case-insensitive file systems",
+                path, existing
+            ),
+            ExtractError::TooManyEntries { limit } => {
+                write!(f, "archive has more than {} entries", limit)
+            }
+            ExtractError::TooLarge { limit } => {
+                write!(f, "archive expands to more than {} bytes", limit)
+            }
+            ExtractError::AlreadyExists { paths } => write!(
+                f,
+                "{} file(s) already exist in the destination: {:?}",
+                paths.len(),
+                paths
+            ),
+            ExtractError::Conflict { path } => write!(
+                f,
+                "{:?} in the destination has a different type than the entry",
+                path
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExtractError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExtractError {
+    fn from(error: io::Error) -> Self {
+        ExtractError::Io(error)
+    }
+}
+
+impl From<ExtractError> for io::Error {
+    fn from(error: ExtractError) -> Self {
+        match error {
+            ExtractError::Io(error) => error,
+            ExtractError::AlreadyExists { .. } | ExtractError::Conflict { .. } => {
+                Error::new(ErrorKind::AlreadyExists, error)
+            }
+            _ => Error::new(ErrorKind::InvalidData, error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileDecision {
+    Created,
+    Overwritten,
+    /// Existing file left alone by `OverwritePolicy::Never`
+    Kept,
+    /// Existing file at least as new as the entry, left alone by `OverwritePolicy::Newer`
+    UpToDate,
+}
+
+impl FileDecision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileDecision::Created => "created",
+            FileDecision::Overwritten => "overwritten",
+            FileDecision::Kept => "kept",
+            FileDecision::UpToDate => "up to date",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FileReport {
+    /// path relative to the destination
+    pub path: PathBuf,
+    pub decision: FileDecision,
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractSummary {
+    pub extracted_files: usize,
+    pub extracted_dirs: usize,
+    pub skipped: usize,
+    pub files: Vec<FileReport>,
+}
+
+impl ExtractSummary {
+    pub fn count(&self, decision: FileDecision) -> usize {
+        self.files
+            .iter()
+            .filter(|report| report.decision == decision)
+            .count()
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{} created, {} overwritten, {} kept, {} up to date, {} entries skipped",
+            self.count(FileDecision::Created),
+            self.count(FileDecision::Overwritten),
+            self.count(FileDecision::Kept),
+            self.count(FileDecision::UpToDate),
+            self.skipped
+        );
+        self.files
+            .iter()
+            .for_each(|report| println!("  {:<11} {:?}", report.decision.as_str(), report.path));
+    }
+}
+
+pub fn extract_entries(
+    format: &dyn PayloadFormat,
+    reader: &mut dyn ReadSeek,
+    destination: &Path,
+    options: &ExtractOptions,
+    progress: &mut dyn Progress,
+) -> Result<ExtractSummary, ExtractError> {
+    let only = build_glob_set(&options.only)?;
+    let strip_prefix = match &options.strip_prefix {
+        Some(prefix) => split_path(prefix)?,
+        None => vec![],
+    };
+    let select = |components: &[String]| -> Option<PathBuf> {
+        if let Some(only) = &only {
+            if !only.is_match(components.join("/")) {
+                return None;
+            }
+        }
+        if !components.starts_with(&strip_prefix) || components.len() == strip_prefix.len() {
+            return None;
+        }
+        Some(components[strip_prefix.len()..].iter().collect())
+    };
+
+    // Checked up front so a refused extraction leaves the destination untouched
+    if options.overwrite == OverwritePolicy::Fail {
+        let mut existing = vec![];
+        for entry_info in format.list_from_reader(reader)? {
+            if entry_info.is_dir() {
+                continue;
+            }
+            if let Some(relative) = select(&split_path(&entry_info.path)?) {
+                if destination.join(&relative).exists() {
+                    existing.push(relative);
+                }
+            }
+        }
+        if !existing.is_empty() {
+            return Err(ExtractError::AlreadyExists { paths: existing });
+        }
+        reader.seek(SeekFrom::Start(0))?;
+    }
+
+    let total_size = format.total_size(reader)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    fs::create_dir_all(destination)?;
+    let mut extraction = Extraction {
+        destination: destination.canonicalize()?,
+        options,
+        seen: HashMap::new(),
+        entries: 0,
+        total_size: 0,
+        summary: ExtractSummary::default(),
+        tracker: Tracker::new(progress, Phase::Extracting, total_size),
+    };
+
+    // The visitor can only return io errors, so the typed one is kept aside
+    let mut rejection = None;
+    let result = format.for_each_entry(reader, &mut |entry_info, content| {
+        let result =
+            split_path(&entry_info.path).and_then(|components| match select(&components) {
+                Some(relative) => extraction.entry(&entry_info, &relative, content),
+                None => {
+                    extraction.summary.skipped += 1;
+                    extraction.tracker.advance(entry_info.size);
+                    Ok(())
+                }
+            });
+        result.map_err(|error| {
+            let io_error = Error::other(error.to_string());
+            rejection = Some(error);
+            io_error
+        })
+    });
+
+    match (result, rejection) {
+        (_, Some(error)) => Err(error),
+        (Err(error), None) => Err(error.into()),
+        (Ok(()), None) => {
+            extraction.tracker.finish();
+            Ok(extraction.summary)
+        }
+    }
+}
+
+struct Extraction<'a, 'p> {
+    /// canonical, so links and writes can be checked against it
+    destination: PathBuf,
+    options: &'a ExtractOptions,
+    /// Lowercased relative path of every entry and of every parent of one, to the path
+    /// in its stored case and, for entries, the entry path
+    seen: HashMap<String, (String, Option<String>)>,
+    entries: usize,
+    total_size: u64,
+    summary: ExtractSummary,
+    tracker: Tracker<'p>,
+}
+
+impl Extraction<'_, '_> {
+    fn entry(
+        &mut self,
+        entry_info: &EntryInfo,
+        relative: &Path,
+        content: &mut dyn Read,
+    ) -> Result<(), ExtractError> {
+        let path = &entry_info.path;
+
+        self.entries += 1;
+        if self.entries > self.options.limits.max_entries {
+            return Err(ExtractError::TooManyEntries {
+                limit: self.options.limits.max_entries,
+            });
+        }
+
+        // Parents count too: `Dir/` and `dir/file` would merge into one directory
+        let parts = relative
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>();
+        for end in 1..=parts.len() {
+            let key = parts[..end].join("/");
+            let is_entry = end == parts.len();
+            match self.seen.entry(key.to_lowercase()) {
+                hash_map::Entry::Occupied(mut occupied) => {
+                    let (stored, entry) = occupied.get_mut();
+                    if *stored != key {
+                        return Err(ExtractError::CaseCollision {
+                            path: path.clone(),
+                            existing: entry.clone().unwrap_or_else(|| stored.clone()),
+                        });
+                    }
+                    if is_entry {
+                        if entry.is_some() {
+                            return Err(ExtractError::Duplicate { path: path.clone() });
+                        }
+                        *entry = Some(path.clone());
+                    }
+                }
+                hash_map::Entry::Vacant(vacant) => {
+                    vacant.insert((key, is_entry.then(|| path.clone())));
+                }
+            }
+        }
+
+        let target = self.destination.join(relative);
+        match entry_info.kind {
+            EntryKind::Dir => {
+                self.resolve_parent(&target, path)?;
+                match fs::symlink_metadata(&target) {
+                    Ok(metadata) if !metadata.is_dir() => {
+                        return Err(ExtractError::Conflict { path: target })
+                    }
+                    _ => fs::create_dir_all(&target)?,
+                }
+                self.summary.extracted_dirs += 1;
+                self.tracker.entry(path, 0);
+                Ok(())
+            }
+            EntryKind::File | EntryKind::Symlink => {
+                let parent = self.resolve_parent(&target, path)?;
+                let decision = decide(&target, entry_info, self.options.overwrite)?;
+                let mut written = entry_info.size;
+                if let FileDecision::Created | FileDecision::Overwritten = decision {
+                    if decision == FileDecision::Overwritten {
+                        fs::remove_file(&target)?;
+                    }
+                    if entry_info.kind == EntryKind::Symlink {
+                        self.write_symlink(&target, &parent, entry_info, content)?;
+                    } else {
+                        written = self.write_file(&target, entry_info, content)?;
+                    }
+                    self.summary.extracted_files += 1;
+                }
+                self.tracker.entry(path, written);
+                self.summary.files.push(FileReport {
+                    path: relative.to_path_buf(),
+                    decision,
+                });
+                Ok(())
+            }
+            kind => Err(ExtractError::UnsupportedEntry {
+                path: path.clone(),
+                kind,
+            }),
+        }
+    }
+
+    /// Creates the parent directories and makes sure no link leads them outside the destination.
+    /// Returns where the parent really is.
+    fn resolve_parent(&self, target: &Path, path: &str) -> Result<PathBuf, ExtractError> {
+        let parent = target.parent().unwrap_or(&self.destination);
+        fs::create_dir_all(parent)?;
+        let resolved = parent.canonicalize()?;
+        if !resolved.starts_with(&self.destination) {
+            return Err(ExtractError::SymlinkEscape {
+                path: String::from(path),
+                target: resolved.to_string_lossy().into_owned(),
+            });
+        }
+        Ok(resolved)
+    }
+
+    fn write_file(
+        &mut self,
+        target: &Path,
+        entry_info: &EntryInfo,
+        content: &mut dyn Read,
+    ) -> Result<u64, ExtractError> {
+        let mut file = File::create(target)?;
+        let remaining = self.options.limits.max_total_size - self.total_size;
+        let written = io::copy(&mut content.take(remaining.saturating_add(1)), &mut file)?;
+        if written > remaining {
+            drop(file);
+            fs::remove_file(target)?;
+            return Err(ExtractError::TooLarge {
+                limit: self.options.limits.max_total_size,
+            });
+        }
+        self.total_size += written;
+
+        if let Some(mtime) = entry_info.mtime {
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+        }
+
+        #[cfg(unix)]
+        if let Some(mode) = entry_info.mode {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(mode & 0o777))?;
+        }
+
+        Ok(written)
+    }
+
+    /// Only relative targets that stay inside the destination are created.
+    /// `parent` is the canonical directory of the link, where its target is resolved from.
+    fn write_symlink(
+        &mut self,
+        target: &Path,
+        parent: &Path,
+        entry_info: &EntryInfo,
+        content: &mut dyn Read,
+    ) -> Result<(), ExtractError> {
+        let link_target = match &entry_info.link_target {
+            Some(link_target) => link_target.clone(),
+            // zip stores the target as the entry's content
+            None => {
+                let mut link_target = String::new();
+                content.take(4096).read_to_string(&mut link_target)?;
+                link_target
+            }
+        };
+        let escape = || ExtractError::SymlinkEscape {
+            path: entry_info.path.clone(),
+            target: link_target.clone(),
+        };
+
+        if link_target.is_empty() || link_target.starts_with(['/', '\\']) {
+            return Err(escape());
+        }
+        // `..` only leads the target: past a component that is or later becomes a link,
+        // `..` is the parent of wherever that link points, not the lexical parent
+        let mut resolved = parent.to_path_buf();
+        let mut descended = false;
+        for part in link_target.split(['/', '\\']) {
+            match part {
+                "" | "." => {}
+                ".." if descended => return Err(escape()),
+                ".." => {
+                    resolved.pop();
+                    if !resolved.starts_with(&self.destination) {
+                        return Err(escape());
+                    }
+                }
+                _ if part.contains(':') => return Err(escape()),
+                _ => {
+                    descended = true;
+                    resolved.push(part);
+                    // Links already in the destination, extracted or not, are followed
+                    if fs::symlink_metadata(&resolved).is_ok() {
+                        resolved = resolved.canonicalize().map_err(|_| escape())?;
+                        if !resolved.starts_with(&self.destination) {
+                            return Err(escape());
+                        }
+                    }
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&link_target, target)?;
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(link_target.replace('/', "\\"), target)?;
+
+        Ok(())
+    }
+}
+
+fn decide(
+    target: &Path,
+    entry_info: &EntryInfo,
+    policy: OverwritePolicy,
+) -> Result<FileDecision, ExtractError> {
+    let metadata = match fs::symlink_metadata(target) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(FileDecision::Created),
+        Err(error) => return Err(error.into()),
+    };
+    if metadata.is_dir() {
+        return Err(ExtractError::Conflict {
+            path: target.to_path_buf(),
+        });
+    }
+
+    match policy {
+        OverwritePolicy::Always => Ok(FileDecision::Overwritten),
+        OverwritePolicy::Never => Ok(FileDecision::Kept),
+        OverwritePolicy::Newer => {
+            let existing_mtime = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0);
+            // Without a timestamp the entry cannot be shown to be newer
+            match entry_info.mtime {
+                Some(mtime) if mtime > existing_mtime => Ok(FileDecision::Overwritten),
+                _ => Ok(FileDecision::UpToDate),
+            }
+        }
+        OverwritePolicy::Fail => Err(ExtractError::AlreadyExists {
+            paths: vec![target.to_path_buf()],
+        }),
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> io::Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern.trim_end_matches('/'))
+            .literal_separator(true)
+            .build()
+            .map_err(|error| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid pattern {:?}: {}", pattern, error),
+                )
+            })?;
+        builder.add(glob);
+    }
+    let glob_set = builder
+        .build()
+        .map_err(|error| Error::new(ErrorKind::InvalidInput, format!("{}", error)))?;
+    Ok(Some(glob_set))
+}
+
+/// Device names Windows resolves in every directory, with or without an extension
+pub(crate) const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9", "CONIN$",
+    "CONOUT$",
+];
+
+/// Splits an archive path into its components.
+/// Anything that could leave the destination or name a device is rejected.
+fn split_path(path: &str) -> Result<Vec<String>, ExtractError> {
+    if path.starts_with(['/', '\\']) {
+        return Err(ExtractError::AbsolutePath {
+            path: String::from(path),
+        });
+    }
+
+    let mut components = vec![];
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => {
+                return Err(ExtractError::ParentTraversal {
+                    path: String::from(path),
+                })
+            }
+            _ if part.contains(':') => {
+                return Err(ExtractError::DrivePrefix {
+                    path: String::from(path),
+                })
+            }
+            _ if is_reserved_name(part) => {
+                return Err(ExtractError::ReservedName {
+                    path: String::from(path),
+                })
+            }
+            _ => components.push(String::from(part)),
+        }
+    }
+    Ok(components)
+}
+
+/// Whether Windows opens `part` as a device, `nul.txt` and `CON ` included
+pub(crate) fn is_reserved_name(part: &str) -> bool {
+    let stem = part.split('.').next().unwrap_or(part).trim_end_matches(' ');
+    RESERVED_NAMES
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(stem))
+}
+
+/// Archives crafted to write outside the destination or to exhaust the disk.
+/// Every one of them must be refused before anything lands outside the destination.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_format::ArchiveFormat;
+    use crate::progress::NoProgress;
+    use std::io::{Cursor, Write};
+    use tempfile::TempDir;
+    use zip::{write::FileOptions, ZipWriter};
+
+    /// Zip with the given (name, content) file entries, names stored as is
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Sets the unix mode, file type included, of the zip entry `name`.
+    /// The zip crate only writes regular files, directories and links.
+    fn with_unix_mode(mut zip: Vec<u8>, name: &str, mode: u32) -> Vec<u8> {
+        let signature = [0x50, 0x4b, 0x01, 0x02];
+        let mut offset = 0;
+        while offset + 46 <= zip.len() {
+            let name_len = u16::from_le_bytes([zip[offset + 28], zip[offset + 29]]) as usize;
+            if zip[offset..offset + 4] == signature
+                && zip.get(offset + 46..offset + 46 + name_len) == Some(name.as_bytes())
+            {
+                // Made by unix, so the mode is read from the external attributes
+                zip[offset + 5] = 3;
+                zip[offset + 38..offset + 42].copy_from_slice(&(mode << 16).to_le_bytes());
+                return zip;
+            }
+            offset += 1;
+        }
+        panic!("no central directory entry {:?}", name);
+    }
+
+    enum TarEntry<'a> {
+        File(&'a str, &'a [u8]),
+        Symlink(&'a str, &'a str),
+        Dir(&'a str),
+        /// Hardlinks, fifos and devices
+        Other(&'a str, tar::EntryType),
+    }
+
+    /// tar.zst payload with names and link targets written raw, bypassing the checks
+    /// the tar crate does on paths
+    fn tar_zst(entries: &[TarEntry]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for entry in entries {
+            let mut header = tar::Header::new_gnu();
+            let (name, content): (&str, &[u8]) = match entry {
+                TarEntry::File(name, content) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    (name, content)
+                }
+                TarEntry::Symlink(name, target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_link_name_literal(target).unwrap();
+                    (name, &[])
+                }
+                TarEntry::Dir(name) => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    (name, &[])
+                }
+                TarEntry::Other(name, entry_type) => {
+                    header.set_entry_type(*entry_type);
+                    header.set_link_name_literal("file").unwrap();
+                    (name, &[])
+                }
+            };
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, content).unwrap();
+        }
+        zstd::encode_all(&builder.into_inner().unwrap()[..], 3).unwrap()
+    }
+
+    /// Destination inside a scratch dir, so escapes land next to it where they can be seen
+    struct Scratch {
+        dir: TempDir,
+    }
+
+    impl Scratch {
+        fn new() -> Scratch {
+            let dir = tempfile::tempdir().unwrap();
+            fs::create_dir(dir.path().join("dest")).unwrap();
+            Scratch { dir }
+        }
+
+        fn destination(&self) -> PathBuf {
+            self.dir.path().join("dest")
+        }
+
+        fn extract(
+            &self,
+            format: ArchiveFormat,
+            payload: Vec<u8>,
+            options: &ExtractOptions,
+        ) -> Result<(), ExtractError> {
+            self.summary(format, payload, options).map(|_| ())
+        }
+
+        fn summary(
+            &self,
+            format: ArchiveFormat,
+            payload: Vec<u8>,
+            options: &ExtractOptions,
+        ) -> Result<ExtractSummary, ExtractError> {
+            format.extract_with_options(
+                Cursor::new(payload),
+                self.destination(),
+                options,
+                &mut NoProgress,
+            )
+        }
+
+        /// Every file under the destination, `/`-separated and sorted
+        fn files(&self) -> Vec<String> {
+            let destination = self.destination();
+            let mut files = walkdir::WalkDir::new(&destination)
+                .into_iter()
+                .map(|entry| entry.unwrap())
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| {
+                    let relative = entry.path().strip_prefix(&destination).unwrap();
+                    relative.to_str().unwrap().replace('\\', "/")
+                })
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        }
+
+        /// Everything in the scratch dir outside the destination
+        fn outside(&self) -> Vec<String> {
+            let mut names = fs::read_dir(self.dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| name != "dest")
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        }
+    }
+
+    #[test]
+    fn zip_slip_is_refused() {
+        for name in ["../evil", "sub/../../evil", "..\\evil", "sub\\..\\..\\evil"] {
+            let scratch = Scratch::new();
+            let payload = zip(&[("ok", b"ok"), (name, b"evil")]);
+            let result = scratch.extract(ArchiveFormat::Zip, payload, &ExtractOptions::default());
+            assert!(
+                matches!(result, Err(ExtractError::ParentTraversal { .. })),
+                "{}: {:?}",
+                name,
+                result
+            );
+            assert!(scratch.outside().is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn absolute_paths_are_refused() {
+        for name in [
+            "/tmp/evil",
+            "\\evil",
+            "\\\\server\\share\\evil",
+            "//server/share/evil",
+        ] {
+            let scratch = Scratch::new();
+            let result = scratch.extract(
+                ArchiveFormat::Zip,
+                zip(&[(name, b"evil")]),
+                &ExtractOptions::default(),
+            );
+            assert!(
+                matches!(result, Err(ExtractError::AbsolutePath { .. })),
+                "{}: {:?}",
+                name,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn drive_letter_paths_are_refused() {
+        for name in ["C:\\Windows\\evil", "C:/evil", "C:evil", "sub/file:stream"] {
+            let scratch = Scratch::new();
+            let result = scratch.extract(
+                ArchiveFormat::Zip,
+                zip(&[(name, b"evil")]),
+                &ExtractOptions::default(),
+            );
+            assert!(
+                matches!(result, Err(ExtractError::DrivePrefix { .. })),
+                "{}: {:?}",
+                name,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn zip_bomb_stops_at_the_size_limit() {
+        let scratch = Scratch::new();
+        // A few hundred bytes compressed
+        let bomb = vec![0; 1024 * 1024];
+        let payload = zip(&[("small", b"small"), ("bomb", &bomb)]);
+        let options = ExtractOptions {
+            limits: ExtractLimits {
+                max_total_size: 64 * 1024,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = scratch.extract(ArchiveFormat::Zip, payload, &options);
+        assert!(
+            matches!(result, Err(ExtractError::TooLarge { limit: 65536 })),
+            "{:?}",
+            result
+        );
+        // The partial file is not left behind
+        assert!(!scratch.destination().join("bomb").exists());
+    }
+
+    #[test]
+    fn zip_bomb_stops_at_the_entry_limit() {
+        let scratch = Scratch::new();
+        let names = (0..20).map(|index| index.to_string()).collect::<Vec<_>>();
+        let files = names
+            .iter()
+            .map(|name| (name.as_str(), &b""[..]))
+            .collect::<Vec<_>>();
+        let options = ExtractOptions {
+            limits: ExtractLimits {
+                max_entries: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = scratch.extract(ArchiveFormat::Zip, zip(&files), &options);
+        assert!(
+            matches!(result, Err(ExtractError::TooManyEntries { limit: 10 })),
+            "{:?}",
+            result
+        );
+    }
+
+    /// Extracts the zip and tar.zst payloads and returns their results
+    fn both(
+        zip_payload: Vec<u8>,
+        tar_payload: Vec<u8>,
+    ) -> [(ArchiveFormat, Result<(), ExtractError>); 2] {
+        [
+            (ArchiveFormat::Zip, zip_payload),
+            (ArchiveFormat::TarZstd, tar_payload),
+        ]
+        .map(|(format, payload)| {
+            let result = Scratch::new().extract(format, payload, &ExtractOptions::default());
+            (format, result)
+        })
+    }
+
+    #[test]
+    fn reserved_names_are_refused() {
+        for name in ["CON", "sub/nul.txt", "aux ", "Com1.log", "conout$"] {
+            let results = both(
+                zip(&[(name, b"device")]),
+                tar_zst(&[TarEntry::File(name, b"device")]),
+            );
+            for (format, result) in results {
+                assert!(
+                    matches!(result, Err(ExtractError::ReservedName { .. })),
+                    "{} {}: {:?}",
+                    format,
+                    name,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn duplicates_are_refused() {
+        for (first, second) in [("file", "file"), ("sub/file", "sub//file"), ("d/", "d/")] {
+            let results = both(
+                zip(&[(first, b"1"), (second, b"2")]),
+                tar_zst(&[TarEntry::File(first, b"1"), TarEntry::File(second, b"2")]),
+            );
+            for (format, result) in results {
+                assert!(
+                    matches!(result, Err(ExtractError::Duplicate { .. })),
+                    "{} {} {}: {:?}",
+                    format,
+                    first,
+                    second,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn case_collisions_are_refused() {
+        for (first, second) in [
+            ("File", "file"),
+            ("Dir/", "dir/file"),
+            ("A/x", "a/y"),
+            ("a/B/x", "a/b"),
+        ] {
+            let zip_payload = zip(&[(first, b"1"), (second, b"2")]);
+            let tar_entry = |name: &'static str| match name {
+                name if name.ends_with('/') => TarEntry::Dir(name),
+                name => TarEntry::File(name, b""),
+            };
+            let tar_payload = tar_zst(&[tar_entry(first), tar_entry(second)]);
+            for (format, result) in both(zip_payload, tar_payload) {
+                assert!(
+                    matches!(result, Err(ExtractError::CaseCollision { .. })),
+                    "{} {} {}: {:?}",
+                    format,
+                    first,
+                    second,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn same_case_parents_are_not_collisions() {
+        let results = both(
+            zip(&[("dir/a", b"a"), ("dir/", b""), ("dir/b", b"b")]),
+            tar_zst(&[
+                TarEntry::File("dir/a", b"a"),
+                TarEntry::Dir("dir/"),
+                TarEntry::File("dir/b", b"b"),
+            ]),
+        );
+        for (format, result) in results {
+            assert!(result.is_ok(), "{}: {:?}", format, result);
+        }
+    }
+
+    #[test]
+    fn unsupported_entries_are_refused() {
+        const S_IFIFO: u32 = 0o010000;
+        let zip_payload = with_unix_mode(zip(&[("fifo", b"")]), "fifo", S_IFIFO | 0o644);
+        let tar_payload = tar_zst(&[TarEntry::Other("fifo", tar::EntryType::Fifo)]);
+        for (format, result) in both(zip_payload, tar_payload) {
+            assert!(
+                matches!(
+                    result,
+                    Err(ExtractError::UnsupportedEntry {
+                        kind: EntryKind::Special,
+                        ..
+                    })
+                ),
+                "{}: {:?}",
+                format,
+                result
+            );
+        }
+
+        // Zip has no hardlinks
+        let scratch = Scratch::new();
+        let payload = tar_zst(&[
+            TarEntry::File("file", b"content"),
+            TarEntry::Other("hardlink", tar::EntryType::Link),
+        ]);
+        let result = scratch.extract(ArchiveFormat::TarZstd, payload, &ExtractOptions::default());
+        assert!(
+            matches!(
+                result,
+                Err(ExtractError::UnsupportedEntry {
+                    kind: EntryKind::Hardlink,
+                    ..
+                })
+            ),
+            "{:?}",
+            result
+        );
+        assert!(!scratch.destination().join("hardlink").exists());
+    }
+
+    /// The same files as a zip and as a tar.zst payload
+    fn payloads(files: &[(&str, &[u8])]) -> [(ArchiveFormat, Vec<u8>); 2] {
+        let tar_entries = files
+            .iter()
+            .map(|(name, content)| TarEntry::File(name, content))
+            .collect::<Vec<_>>();
+        [
+            (ArchiveFormat::Zip, zip(files)),
+            (ArchiveFormat::TarZstd, tar_zst(&tar_entries)),
+        ]
+    }
+
+    const INSTALLER: &[(&str, &[u8])] = &[
+        ("bin/app.exe", b"exe"),
+        ("bin/app.dll", b"dll"),
+        ("config/app.toml", b"toml"),
+        ("readme.txt", b"txt"),
+    ];
+
+    #[test]
+    fn only_extracts_matching_entries() {
+        for (format, payload) in payloads(INSTALLER) {
+            let scratch = Scratch::new();
+            let options = ExtractOptions {
+                only: vec!["bin/*.dll".to_string(), "config/*".to_string()],
+                ..Default::default()
+            };
+            let summary = scratch.summary(format, payload, &options).unwrap();
+            assert_eq!(
+                scratch.files(),
+                ["bin/app.dll", "config/app.toml"],
+                "{}",
+                format
+            );
+            assert_eq!(summary.skipped, 2, "{}", format);
+        }
+
+        // `*` stays within one directory
+        for (format, payload) in payloads(INSTALLER) {
+            let scratch = Scratch::new();
+            let options = ExtractOptions {
+                only: vec!["*.txt".to_string()],
+                ..Default::default()
+            };
+            scratch.extract(format, payload, &options).unwrap();
+            assert_eq!(scratch.files(), ["readme.txt"], "{}", format);
+        }
+    }
+
+    #[test]
+    fn strip_prefix_extracts_one_directory() {
+        for (format, payload) in payloads(INSTALLER) {
+            let scratch = Scratch::new();
+            let options = ExtractOptions {
+                strip_prefix: Some("bin/".to_string()),
+                ..Default::default()
+            };
+            let summary = scratch.summary(format, payload, &options).unwrap();
+            assert_eq!(scratch.files(), ["app.dll", "app.exe"], "{}", format);
+            assert_eq!(summary.skipped, 2, "{}", format);
+        }
+    }
+
+    #[test]
+    fn only_matches_before_the_prefix_is_stripped() {
+        for (format, payload) in payloads(INSTALLER) {
+            let scratch = Scratch::new();
+            let options = ExtractOptions {
+                only: vec!["bin/app.dll".to_string()],
+                strip_prefix: Some("bin".to_string()),
+                ..Default::default()
+            };
+            scratch.extract(format, payload, &options).unwrap();
+            assert_eq!(scratch.files(), ["app.dll"], "{}", format);
+            assert_eq!(
+                fs::read(scratch.destination().join("app.dll")).unwrap(),
+                b"dll"
+            );
+        }
+
+        // A prefix that escapes is refused like an entry would be
+        let options = ExtractOptions {
+            strip_prefix: Some("../bin".to_string()),
+            ..Default::default()
+        };
+        let result = Scratch::new().extract(ArchiveFormat::Zip, zip(INSTALLER), &options);
+        assert!(
+            matches!(result, Err(ExtractError::ParentTraversal { .. })),
+            "{:?}",
+            result
+        );
+    }
+
+    const PATCH: &[(&str, &[u8])] = &[("app.exe", b"new"), ("lib/new.dll", b"dll")];
+
+    /// Destination holding an older `app.exe` and a file the payload does not know about
+    fn existing_install() -> Scratch {
+        let scratch = Scratch::new();
+        fs::write(scratch.destination().join("app.exe"), "old").unwrap();
+        fs::write(scratch.destination().join("user.cfg"), "mine").unwrap();
+        scratch
+    }
+
+    fn with_overwrite(overwrite: OverwritePolicy) -> ExtractOptions {
+        ExtractOptions {
+            overwrite,
+            ..Default::default()
+        }
+    }
+
+    fn read(scratch: &Scratch, path: &str) -> String {
+        fs::read_to_string(scratch.destination().join(path)).unwrap()
+    }
+
+    #[test]
+    fn overwrite_always_replaces_existing_files() {
+        for (format, payload) in payloads(PATCH) {
+            let scratch = existing_install();
+            let options = with_overwrite(OverwritePolicy::Always);
+            let summary = scratch.summary(format, payload, &options).unwrap();
+
+            assert_eq!(summary.count(FileDecision::Overwritten), 1, "{}", format);
+            assert_eq!(summary.count(FileDecision::Created), 1, "{}", format);
+            assert_eq!(read(&scratch, "app.exe"), "new");
+            assert_eq!(read(&scratch, "user.cfg"), "mine");
+            assert_eq!(scratch.files(), ["app.exe", "lib/new.dll", "user.cfg"]);
+        }
+    }
+
+    #[test]
+    fn overwrite_never_keeps_existing_files() {
+        for (format, payload) in payloads(PATCH) {
+            let scratch = existing_install();
+            let options = with_overwrite(OverwritePolicy::Never);
+            let summary = scratch.summary(format, payload, &options).unwrap();
+
+            assert_eq!(summary.count(FileDecision::Kept), 1, "{}", format);
+            assert_eq!(summary.count(FileDecision::Created), 1, "{}", format);
+            assert_eq!(read(&scratch, "app.exe"), "old");
+            assert_eq!(scratch.files(), ["app.exe", "lib/new.dll", "user.cfg"]);
+        }
+    }
+
+    #[test]
+    fn overwrite_newer_compares_modification_times() {
+        let dated = |year: u16| {
+            FileOptions::default()
+                .last_modified_time(zip::DateTime::from_date_and_time(year, 1, 1, 0, 0, 0).unwrap())
+        };
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, year) in [("app.exe", 2100), ("user.cfg", 1990)] {
+            writer.start_file(name, dated(year)).unwrap();
+            writer.write_all(b"new").unwrap();
+        }
+        let payload = writer.finish().unwrap().into_inner();
+
+        let scratch = existing_install();
+        let options = with_overwrite(OverwritePolicy::Newer);
+        let summary = scratch
+            .summary(ArchiveFormat::Zip, payload, &options)
+            .unwrap();
+        assert_eq!(summary.count(FileDecision::Overwritten), 1);
+        assert_eq!(summary.count(FileDecision::UpToDate), 1);
+        assert_eq!(read(&scratch, "app.exe"), "new");
+        assert_eq!(read(&scratch, "user.cfg"), "mine");
+
+        // Tar entries written without a time are dated at the epoch
+        let scratch = existing_install();
+        let payload = tar_zst(&[TarEntry::File("app.exe", b"new")]);
+        let summary = scratch
+            .summary(ArchiveFormat::TarZstd, payload, &options)
+            .unwrap();
+        assert_eq!(summary.count(FileDecision::UpToDate), 1);
+        assert_eq!(read(&scratch, "app.exe"), "old");
+    }
+
+    #[test]
+    fn overwrite_fail_refuses_before_writing() {
+        for (format, payload) in payloads(PATCH) {
+            let scratch = existing_install();
+            let options = with_overwrite(OverwritePolicy::Fail);
+            match scratch.summary(format, payload, &options) {
+                Err(ExtractError::AlreadyExists { paths }) => {
+                    assert_eq!(paths, [PathBuf::from("app.exe")], "{}", format)
+                }
+                other => panic!("{}: {:?}", format, other),
+            }
+            assert_eq!(scratch.files(), ["app.exe", "user.cfg"]);
+        }
+
+        for (format, payload) in payloads(PATCH) {
+            let scratch = Scratch::new();
+            let options = with_overwrite(OverwritePolicy::Fail);
+            let summary = scratch.summary(format, payload, &options).unwrap();
+            assert_eq!(summary.count(FileDecision::Created), 2, "{}", format);
+        }
+    }
+
+    #[test]
+    fn directories_in_the_way_are_conflicts() {
+        for (format, payload) in payloads(PATCH) {
+            let scratch = Scratch::new();
+            fs::create_dir(scratch.destination().join("app.exe")).unwrap();
+            let result = scratch.extract(format, payload, &ExtractOptions::default());
+            assert!(
+                matches!(result, Err(ExtractError::Conflict { .. })),
+                "{}: {:?}",
+                format,
+                result
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    mod symlinks {
+        use super::*;
+
+        fn assert_escape(result: Result<(), ExtractError>, scratch: &Scratch) {
+            assert!(
+                matches!(result, Err(ExtractError::SymlinkEscape { .. })),
+                "{:?}",
+                result
+            );
+            assert!(scratch.outside().is_empty(), "{:?}", scratch.outside());
+        }
+
+        #[test]
+        fn links_leaving_the_destination_are_refused() {
+            for target in ["..", "../x", "sub/../../x", "/etc/passwd", "C:/x"] {
+                let scratch = Scratch::new();
+                let payload = tar_zst(&[TarEntry::Symlink("link", target)]);
+                let result =
+                    scratch.extract(ArchiveFormat::TarZstd, payload, &ExtractOptions::default());
+                assert_escape(result, &scratch);
+            }
+        }
+
+        #[test]
+        fn links_inside_the_destination_are_created() {
+            let scratch = Scratch::new();
+            let payload = tar_zst(&[
+                TarEntry::File("lib/real.so", b"so"),
+                TarEntry::Symlink("lib/app.so", "real.so"),
+                TarEntry::Symlink("bin/app.so", "../lib/real.so"),
+            ]);
+            scratch
+                .extract(ArchiveFormat::TarZstd, payload, &ExtractOptions::default())
+                .unwrap();
+            let destination = scratch.destination();
+            assert_eq!(fs::read(destination.join("lib/app.so")).unwrap(), b"so");
+            assert_eq!(fs::read(destination.join("bin/app.so")).unwrap(), b"so");
+        }
+
+        #[test]
+        fn depth_comes_from_where_the_link_really_is() {
+            // a/a is the destination itself, so the two `..` leave it
+            let scratch = Scratch::new();
+            let payload = tar_zst(&[
+                TarEntry::Symlink("a", "."),
+                TarEntry::Symlink("a/a/l", "../../x"),
+            ]);
+            let result =
+                scratch.extract(ArchiveFormat::TarZstd, payload, &ExtractOptions::default());
+            assert_escape(result, &scratch);
+            assert!(fs::symlink_metadata(scratch.destination().join("l")).is_err());
+        }
+
+        #[test]
+        fn parent_after_a_link_is_refused() {
+            // `s/..` is the parent of where s points, the scratch dir here
+            let scratch = Scratch::new();
+            let payload = tar_zst(&[
+                TarEntry::Symlink("d/s", ".."),
+                TarEntry::Symlink("d/l", "s/../x"),
+            ]);
+            let result =
+                scratch.extract(ArchiveFormat::TarZstd, payload, &ExtractOptions::default());
+            assert_escape(result, &scratch);
+        }
+
+        #[test]
+        fn writes_through_an_extracted_link_stay_inside() {
+            let scratch = Scratch::new();
+            let payload = tar_zst(&[
+                TarEntry::Symlink("link", "sub"),
+                TarEntry::File("sub/keep", b""),
+                TarEntry::File("link/file", b"through"),
+            ]);
+            scratch
+                .extract(ArchiveFormat::TarZstd, payload, &ExtractOptions::default())
+                .unwrap();
+            assert_eq!(
+                fs::read(scratch.destination().join("sub/file")).unwrap(),
+                b"through"
+            );
+            assert!(scratch.outside().is_empty());
+        }
+
+        #[test]
+        fn writes_through_a_link_leaving_the_destination_are_refused() {
+            // Merging into a destination that already holds a link to the outside
+            let scratch = Scratch::new();
+            let outside = scratch.dir.path().join("outside");
+            fs::create_dir(&outside).unwrap();
+            std::os::unix::fs::symlink(&outside, scratch.destination().join("out")).unwrap();
+
+            let payload = tar_zst(&[TarEntry::File("out/file", b"evil")]);
+            let result =
+                scratch.extract(ArchiveFormat::TarZstd, payload, &ExtractOptions::default());
+            assert!(
+                matches!(result, Err(ExtractError::SymlinkEscape { .. })),
+                "{:?}",
+                result
+            );
+            assert!(is_empty(&outside));
+
+            // Nor can a new link point through it
+            let payload = tar_zst(&[TarEntry::Symlink("l", "out/file")]);
+            let result =
+                scratch.extract(ArchiveFormat::TarZstd, payload, &ExtractOptions::default());
+            assert!(
+                matches!(result, Err(ExtractError::SymlinkEscape { .. })),
+                "{:?}",
+                result
+            );
+        }
+
+        fn is_empty(dir: &Path) -> bool {
+            fs::read_dir(dir).unwrap().next().is_none()
+        }
+    }
+}
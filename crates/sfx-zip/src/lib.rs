@@ -1,2 +1,20 @@
+pub use builder::{SfxZipBuilder, SfxZipError};
+pub use sfx_extractor::SfxZipExtractor;
 pub use sfx_ll;
+pub mod archive_args;
+pub mod archive_format;
+pub mod builder;
+pub mod compression;
+pub mod entrypoint;
+pub mod extract;
+pub mod listing;
+pub mod manifest;
+pub mod metadata;
+pub mod onefile;
+pub mod progress;
+pub mod reproducible;
+pub mod sfx_extractor;
+pub mod source_filter;
+pub mod sources;
+pub mod tar_fns;
 pub mod zip_fns;
@@ -0,0 +1,99 @@
+use crate::reproducible::civil_from_unix;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Hardlink,
+    /// Devices, fifos and anything else that is neither a file nor a link
+    Special,
+}
+
+/// One entry of a payload, as stored; nothing is decompressed to produce it
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryInfo {
+    pub path: String,
+    pub kind: EntryKind,
+    /// Where a symlink or hardlink points, as stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+    pub size: u64,
+    /// `None` for formats compressed as a single stream
+    pub compressed_size: Option<u64>,
+    pub mode: Option<u32>,
+    /// seconds since the unix epoch
+    pub mtime: Option<u64>,
+    /// `None` for formats without per-entry checksums
+    pub crc32: Option<u32>,
+}
+
+impl EntryInfo {
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Dir
+    }
+}
+
+pub fn print(entries: &[EntryInfo], json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(entries).unwrap());
+        return;
+    }
+
+    println!(
+        "{:>12} {:>12} {:>7} {:<19} {:>8}  path",
+        "size", "compressed", "mode", "mtime", "crc32"
+    );
+    for entry in entries {
+        let link_target = entry
+            .link_target
+            .as_ref()
+            .map(|link_target| format!(" -> {}", link_target))
+            .unwrap_or_default();
+        println!(
+            "{:>12} {:>12} {:>7} {:<19} {:>8}  {}{}",
+            entry.size,
+            optional(entry.compressed_size),
+            entry
+                .mode
+                .map(|mode| format!("{:o}", mode & 0o7777))
+                .unwrap_or_else(|| String::from("-")),
+            entry
+                .mtime
+                .map(format_unix_time)
+                .unwrap_or_else(|| String::from("-")),
+            entry
+                .crc32
+                .map(|crc32| format!("{:08x}", crc32))
+                .unwrap_or_else(|| String::from("-")),
+            entry.path,
+            link_target
+        );
+    }
+
+    let total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+    println!("{} entries, {} bytes", entries.len(), total_size);
+}
+
+fn optional(value: Option<u64>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| String::from("-"))
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in UTC
+fn format_unix_time(unix_seconds: u64) -> String {
+    let (year, month, day) = civil_from_unix(unix_seconds);
+    let seconds_of_day = unix_seconds % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
@@ -1,196 +1,234 @@
-mod zip_fns;
-
-use sfx_ll::{self, embedder, extractor};
-use std::{
-    fs::{self},
-    io::{Error, ErrorKind},
-    path::{Path, PathBuf},
+use sfx_ll::extractor;
+use sfx_zip::{
+    archive_args::ArchiveArgs,
+    archive_format::{ArchiveFormat, FORMAT_KEY},
+    extract::{ExtractLimits, OverwritePolicy},
+    listing,
+    manifest::Manifest,
+    onefile,
+    progress::{NoProgress, Progress, ProgressBar},
+    sources::SourceMapping,
+    SfxZipBuilder, SfxZipError, SfxZipExtractor,
 };
+use std::path::PathBuf;
 use structopt::{self, StructOpt};
-use zip_fns::{archive, extract};
 
 #[derive(Debug, StructOpt)]
 struct Opt {
+    /// No progress bar or summaries, only errors
+    #[structopt(short = "q", long, global = true)]
+    quiet: bool,
     // Create archive file
     #[structopt(subcommand)]
     subcommand: Subcommand,
 }
 
-const ENTRYPOINT_KEY: &str = "entrypoint";
-
 #[derive(Debug, StructOpt)]
 enum Subcommand {
     Archive {
-        #[structopt(short = "s", long)]
-        source: PathBuf,
+        /// Package described by an sfx.toml; it replaces -s, -e and the archive flags
+        #[structopt(long)]
+        manifest: Option<PathBuf>,
+        /// Directory or file to archive, repeatable.
+        /// `path=prefix` puts it under prefix inside the archive.
+        #[structopt(
+            short = "s",
+            long,
+            number_of_values = 1,
+            required_unless = "manifest",
+            conflicts_with = "manifest"
+        )]
+        source: Vec<SourceMapping>,
         #[structopt(short = "d", long)]
         destination: PathBuf,
+        /// Defaults to the destination with an .sfx-payload extension
         #[structopt(short = "z", long)]
-        temp_zip_file_name: PathBuf,
-        #[structopt(short = "e", long)]
+        temp_zip_file_name: Option<PathBuf>,
+        #[structopt(short = "e", long, conflicts_with = "manifest")]
         entry_point: Option<PathBuf>,
         #[structopt(short = "f", long)]
         force: bool,
+        /// Make a portable exe: running it extracts into a per-user cache once
+        /// and runs the entrypoint with all of its arguments
+        #[structopt(long)]
+        onefile: bool,
+        #[structopt(flatten)]
+        archive_args: ArchiveArgs,
     },
     Extract {
         #[structopt(short = "d", long)]
         destination: PathBuf,
-        #[structopt(short = "t", long)]
-        temp_zip_file_name: PathBuf,
-        #[structopt(short = "f", long)]
+        #[structopt(short = "f", long, conflicts_with = "overwrite")]
         force: bool,
+        /// Merge into an existing destination instead of requiring it to be empty.
+        /// Decides what happens to files that already exist: always, never, newer or fail.
+        /// Files the payload does not contain are kept.
+        #[structopt(long)]
+        overwrite: Option<OverwritePolicy>,
+        /// Only extract entries whose archive path matches one of these globs
+        #[structopt(long)]
+        only: Vec<String>,
+        /// Remove this leading path from extracted entries, skipping entries outside of it
+        #[structopt(long)]
+        strip_prefix: Option<String>,
+        /// Refuse payloads that expand to more bytes than this
+        #[structopt(long)]
+        max_total_size: Option<u64>,
+        /// Refuse payloads with more entries than this
+        #[structopt(long)]
+        max_entries: Option<usize>,
+        /// Run the embedded entrypoint after extraction and exit with its code
+        #[structopt(short = "r", long)]
+        run: bool,
+        /// Arguments passed to the entrypoint, after `--`
+        #[structopt(last = true)]
+        args: Vec<String>,
+    },
+    /// Print the payload's entries without extracting anything
+    List {
+        #[structopt(long)]
+        json: bool,
     },
 }
 
-fn ensure_gone<P: AsRef<Path>>(filepath: P) {
-    let filepath = filepath.as_ref();
-
-    if !filepath.exists() {
-        return;
-    }
-
-    if filepath.is_dir() {
-        fs::remove_dir_all(filepath).unwrap();
-    } else {
-        fs::remove_file(filepath).unwrap();
+/// Prints `error` the way the CLI always has; validation errors come with the parameters
+fn report(error: SfxZipError, subcommand: &Subcommand) -> Option<std::io::Error> {
+    match error {
+        SfxZipError::Invalid(errors) => {
+            eprintln!("{} error occured", errors.len());
+            errors.iter().for_each(|error| {
+                eprintln!("error: {}", error);
+            });
+            eprintln!("parameters {:?}", subcommand);
+            None
+        }
+        error => {
+            eprintln!("error: {}", error);
+            Some(error.into())
+        }
     }
 }
 
 fn main() -> Result<(), Option<std::io::Error>> {
+    if onefile::is_onefile() {
+        let args = std::env::args().skip(1).collect::<Vec<_>>();
+        match onefile::run(&args) {
+            Ok(exit_code) => std::process::exit(exit_code),
+            Err(error) => {
+                eprintln!("error: {}", error);
+                return Err(Some(error));
+            }
+        }
+    }
+
     let opt = Opt::from_args();
+    let mut progress: Box<dyn Progress> = if opt.quiet {
+        Box::new(NoProgress)
+    } else {
+        Box::new(ProgressBar::new())
+    };
 
     match &opt.subcommand {
         Subcommand::Archive {
+            manifest,
             source,
             destination,
             temp_zip_file_name,
             entry_point,
             force,
+            onefile,
+            archive_args,
         } => {
-            let mut errors: Vec<std::io::Error> = vec![];
-
-            if !source.exists() && !force {
-                errors.push(Error::new(
-                    ErrorKind::Other,
-                    "source does not exist. source must exist.",
-                ));
-            }
-
-            if temp_zip_file_name.exists() && !force {
-                errors.push(Error::new(
-                    ErrorKind::Other,
-                    "temp_zip_file_name already exists. temp_zip_file_name cannot exist.",
-                ));
+            let mut builder = match manifest {
+                Some(manifest) => {
+                    let manifest = Manifest::load(manifest)
+                        .map_err(|errors| report(SfxZipError::Invalid(errors), &opt.subcommand))?;
+                    SfxZipBuilder::from_manifest(&manifest, destination)
+                }
+                None => SfxZipBuilder::from_sources(source.clone(), destination)
+                    .format(archive_args.format)
+                    .options(archive_args.archive_options()),
             }
-
-            if destination.exists() && !force {
-                errors.push(Error::new(
-                    ErrorKind::Other,
-                    "destination already exists. destination cannot exist.",
-                ));
+            .onefile(*onefile)
+            .force(*force);
+            if let Some(temp_zip_file_name) = temp_zip_file_name {
+                builder = builder.temp_payload(temp_zip_file_name);
             }
-
-            if !source.is_dir() {
-                errors.push(Error::new(
-                    ErrorKind::Other,
-                    "source is not a directory. source must be directory.",
-                ));
-            }
-
             if let Some(entry_point) = entry_point {
-                if entry_point.is_absolute() {
-                    errors.push(Error::new(
-                        ErrorKind::Other,
-                        "entry_point cannot be absolute path.",
-                    ));
-                }
-
-                let absolute_entry_point = source.join(entry_point);
-
-                if !absolute_entry_point.exists() {
-                    errors.push(Error::new(ErrorKind::Other, "entry_point does not exist"));
-                }
+                builder = builder.entry_point(entry_point);
             }
 
-            if errors.len() > 0 {
-                eprintln!("{} error occured", errors.len());
-                errors.iter().for_each(|error| {
-                    eprintln!("error: {}", error);
-                });
-                eprintln!("parameters {:?}", &opt.subcommand);
-                return Err(None);
+            let skip_summary = builder
+                .build(progress.as_mut())
+                .map_err(|error| report(error, &opt.subcommand))?;
+            if !opt.quiet {
+                skip_summary.print();
             }
 
-            ensure_gone(destination);
-            ensure_gone(temp_zip_file_name);
-            ensure_gone(destination);
-
-            // Make zip file
-            archive(source, temp_zip_file_name);
-
-            fs::copy(std::env::current_exe().unwrap(), destination).unwrap();
-
-            embedder::with_resource_update_handle(
-                destination.as_path(),
-                Box::new(|handle| {
-                    embedder::embed_binary_as_archive(handle, &temp_zip_file_name.as_path())
-                        .unwrap();
-
-                    if let Some(entry_point) = entry_point {
-                        let entry_point_str = String::from(entry_point.to_str().unwrap());
-                        embedder::embed_custom_string(
-                            handle,
-                            &String::from(ENTRYPOINT_KEY),
-                            &entry_point_str,
-                        );
-                    }
-                }),
-            );
-
             Ok(())
         }
         Subcommand::Extract {
             destination,
-            temp_zip_file_name,
             force,
+            overwrite,
+            only,
+            strip_prefix,
+            max_total_size,
+            max_entries,
+            run,
+            args,
         } => {
-            let mut errors: Vec<std::io::Error> = vec![];
-
-            if destination.exists() && !force {
-                if !destination.is_dir() {
-                    errors.push(Error::new(
-                        ErrorKind::Other,
-                        "destination is not directory.",
-                    ));
-                } else if destination.read_dir()?.count() > 0 {
-                    errors.push(Error::new(ErrorKind::Other, "destination is not empty."));
-                }
+            let mut extractor = SfxZipExtractor::new(destination)
+                .force(*force)
+                .only(only.clone())
+                .limits(ExtractLimits {
+                    max_total_size: max_total_size
+                        .unwrap_or(ExtractLimits::default().max_total_size),
+                    max_entries: max_entries.unwrap_or(ExtractLimits::default().max_entries),
+                });
+            if let Some(overwrite) = overwrite {
+                extractor = extractor.overwrite(*overwrite);
             }
-            if temp_zip_file_name.exists() && !force {
-                errors.push(Error::new(ErrorKind::Other, "tempfile already exist."));
+            if let Some(strip_prefix) = strip_prefix {
+                extractor = extractor.strip_prefix(strip_prefix);
             }
 
-            if errors.len() > 0 {
-                eprintln!("{} error occured", errors.len());
-                errors.iter().for_each(|error| {
-                    eprintln!("error: {}", error);
-                });
-                eprintln!("parameters {:?}", &opt.subcommand);
-                return Err(None);
+            if *run && extractor.entry_point().is_none() {
+                let error = std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--run requires an entrypoint, this archive has none.",
+                );
+                return Err(report(SfxZipError::Invalid(vec![error]), &opt.subcommand));
             }
 
-            // Start
-            ensure_gone(destination);
-            ensure_gone(temp_zip_file_name);
-
-            fs::create_dir(destination)?;
-
-            // let entry_point = extractor::read_custom_string(ENTRYPOINT_KEY);
-            extractor::extract_binary(temp_zip_file_name).unwrap();
+            let summary = extractor
+                .extract(progress.as_mut())
+                .map_err(|error| report(error, &opt.subcommand))?;
+            if !opt.quiet {
+                if overwrite.is_some() {
+                    summary.print();
+                } else if summary.skipped > 0 {
+                    println!(
+                        "extracted {} file(s), skipped {} entries",
+                        summary.extracted_files, summary.skipped
+                    );
+                }
+            }
 
-            extract(temp_zip_file_name, destination);
+            if *run {
+                let exit_code = extractor
+                    .run_entry_point(args)
+                    .map_err(|error| report(error, &opt.subcommand))?;
+                std::process::exit(exit_code);
+            }
 
             Ok(())
         }
+        Subcommand::List { json } => {
+            let format = ArchiveFormat::from_metadata(extractor::read_custom_string(FORMAT_KEY))?;
+            let entries = format.list_from_reader(extractor::PayloadReader::new()?)?;
+            listing::print(&entries, *json);
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,851 @@
+use crate::archive_format::ArchiveFormat;
+use crate::compression::{CompressionOptions, Method};
+use crate::extract::is_reserved_name;
+use crate::metadata::{check_homepage, check_semver, AppMetadata};
+use crate::sources::{check_archive_path, SourceMapping};
+use crate::zip_fns::ArchiveOptions;
+use globset::Glob;
+use ignore::gitignore::GitignoreBuilder;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use toml::Spanned;
+
+/// Conventional manifest file name
+pub const MANIFEST_FILE_NAME: &str = "sfx.toml";
+/// Custom string holding the normalized manifest, see `Manifest::normalized`
+pub const MANIFEST_KEY: &str = "manifest";
+
+/// `sfx.toml`: everything a packaging run needs, instead of CLI flags.
+///
+/// ```toml
+/// [app]
+/// id = "com.example.app"
+/// version = "1.2.0"
+/// display_name = "Example"
+/// publisher = "Example Ltd"
+/// homepage = "https://example.com"
+/// license = "LICENSE"
+/// icon = "assets/app.ico"
+///
+/// [[sources]]
+/// path = "dist"
+/// exclude = ["*.pdb"]
+///
+/// [[sources]]
+/// path = "LICENSE"
+/// prefix = "licenses"
+///
+/// [install]
+/// entrypoint = "app.exe"
+/// preserve = ["config.json"]
+///
+/// [[shortcuts]]
+/// name = "Example"
+/// target = "app.exe"
+///
+/// [compression]
+/// format = "tar.zst"
+/// ```
+///
+/// Relative paths are resolved against the manifest's directory,
+/// paths inside the payload (entrypoint, shortcut targets) against the archive root.
+///
+/// `[hooks]`, `[signing]` and `install.scope = "machine"` are reserved: nothing runs hooks,
+/// signs or installs per machine yet, so manifests using them are rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub app: App,
+    pub sources: Vec<Source>,
+    #[serde(default)]
+    pub install: Install,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shortcuts: Vec<Shortcut>,
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing: Option<Signing>,
+    /// Directory the manifest was loaded from
+    #[serde(skip)]
+    base_dir: PathBuf,
+    /// Content of `app.license`, read on load
+    #[serde(skip)]
+    license_text: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct App {
+    pub id: Spanned<String>,
+    /// Semantic version
+    pub version: Spanned<String>,
+    /// Name shown to users, the id when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<Spanned<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<Spanned<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<Spanned<String>>,
+    /// License text file, relative to the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<Spanned<String>>,
+    /// .ico file, relative to the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<Spanned<String>>,
+}
+
+/// App ids name the app's install directory, so they follow a strict reverse-DNS
+/// grammar: two or more dot separated parts of ASCII letters, digits, '-' and '_',
+/// each starting with a letter or a digit, 255 characters at most.
+pub fn check_app_id(id: &str) -> Result<(), String> {
+    if id.is_empty() {
+        return Err(String::from("cannot be empty"));
+    }
+    if id.len() > 255 {
+        return Err(String::from("must be 255 characters at most"));
+    }
+    if let Some(invalid) = id
+        .chars()
+        .find(|character| !(character.is_ascii_alphanumeric() || "._-".contains(*character)))
+    {
+        return Err(format!(
+            "must only contain ASCII letters, digits, '.', '-' and '_', found {:?}",
+            invalid
+        ));
+    }
+
+    let parts = id.split('.').collect::<Vec<_>>();
+    if parts.len() < 2 {
+        return Err(String::from(
+            "must be reverse-DNS, two or more dot separated parts as in com.example.app",
+        ));
+    }
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(String::from(
+            "must not start or end with '.' nor contain '..'",
+        ));
+    }
+    if let Some(part) = parts
+        .iter()
+        .find(|part| !part.starts_with(|first: char| first.is_ascii_alphanumeric()))
+    {
+        return Err(format!(
+            "must have parts starting with a letter or a digit, {:?} does not",
+            part
+        ));
+    }
+    if is_reserved_name(parts[0]) {
+        return Err(format!(
+            "must not start with {:?}, a device name on Windows",
+            parts[0]
+        ));
+    }
+    Ok(())
+}
+
+/// The name becomes the shortcut's file name
+fn check_shortcut_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err(String::from("cannot be empty"));
+    }
+    if let Some(invalid) = name
+        .chars()
+        .find(|character| character.is_control() || "<>:\"/\\|?*".contains(*character))
+    {
+        return Err(format!("cannot contain {:?}", invalid));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Source {
+    /// A directory or a single file
+    pub path: Spanned<String>,
+    /// Archive directory the source lands in, the root by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<Spanned<String>>,
+    /// Same as `--include`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<Spanned<String>>,
+    /// Same as `--exclude`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<Spanned<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallScope {
+    #[default]
+    User,
+    Machine,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Install {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<Spanned<String>>,
+    #[serde(default = "default_scope")]
+    pub scope: Spanned<InstallScope>,
+    /// Files and directories of the installed app carried into the next version on upgrade,
+    /// e.g. config the app writes next to itself
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preserve: Vec<Spanned<String>>,
+}
+
+impl Default for Install {
+    fn default() -> Self {
+        Install {
+            entrypoint: None,
+            scope: default_scope(),
+            preserve: vec![],
+        }
+    }
+}
+
+/// Scripts inside the payload, to run around installation. Reserved, see `Manifest`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_install: Option<Spanned<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_install: Option<Spanned<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_uninstall: Option<Spanned<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_uninstall: Option<Spanned<String>>,
+}
+
+impl Hooks {
+    pub fn all(&self) -> [(&'static str, &Option<Spanned<String>>); 4] {
+        [
+            ("pre_install", &self.pre_install),
+            ("post_install", &self.post_install),
+            ("pre_uninstall", &self.pre_uninstall),
+            ("post_uninstall", &self.post_uninstall),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShortcutLocation {
+    #[default]
+    StartMenu,
+    Desktop,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Shortcut {
+    pub name: Spanned<String>,
+    pub target: Spanned<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arguments: Vec<String>,
+    #[serde(default)]
+    pub location: ShortcutLocation,
+}
+
+/// Same settings as the archive flags of the CLIs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Compression {
+    #[serde(default = "default_format")]
+    pub format: Spanned<ArchiveFormat>,
+    #[serde(default = "default_method")]
+    pub method: Spanned<Method>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<Spanned<i32>>,
+    /// Extensions stored without compression on top of the defaults
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub store_extensions: Vec<String>,
+    /// `false` compresses every file with `method`, like `--no-store-rules`
+    #[serde(default = "default_true")]
+    pub store_rules: bool,
+    #[serde(default)]
+    pub reproducible: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<usize>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            format: default_format(),
+            method: default_method(),
+            level: None,
+            store_extensions: vec![],
+            store_rules: true,
+            reproducible: false,
+            jobs: None,
+        }
+    }
+}
+
+fn default_format() -> Spanned<ArchiveFormat> {
+    Spanned::new(0..0, ArchiveFormat::default())
+}
+
+fn default_method() -> Spanned<Method> {
+    Spanned::new(0..0, CompressionOptions::default().method)
+}
+
+fn default_scope() -> Spanned<InstallScope> {
+    Spanned::new(0..0, InstallScope::default())
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Reserved, see `Manifest`. Never embedded, see `Manifest::normalized`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Signing {
+    /// Relative to the manifest
+    pub key: Spanned<String>,
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Manifest, Vec<Error>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| {
+            vec![Error::new(
+                error.kind(),
+                format!("cannot read manifest {:?}: {}", path, error),
+            )]
+        })?;
+        Manifest::parse(&text, path)
+    }
+
+    /// `path` is where `text` came from; it names the file in errors
+    /// and relative paths are resolved against its directory
+    pub fn parse(text: &str, path: &Path) -> Result<Manifest, Vec<Error>> {
+        let report = Report { path, text };
+        let mut manifest: Manifest = toml::from_str(text).map_err(|error| {
+            vec![report.error(error.span(), error.message().trim_end().to_string())]
+        })?;
+        manifest.base_dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let mut errors = manifest.validate(&report);
+        if let Some(license) = &manifest.app.license {
+            match fs::read_to_string(manifest.base_dir.join(license.get_ref())) {
+                Ok(license_text) => manifest.license_text = Some(license_text),
+                Err(error) => errors.push(report.error(
+                    license.span(),
+                    format!("cannot read license {:?}: {}", license.get_ref(), error),
+                )),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(manifest)
+    }
+
+    fn validate(&self, report: &Report) -> Vec<Error> {
+        let mut errors = vec![];
+
+        if let Err(message) = check_app_id(self.app.id.get_ref()) {
+            errors.push(report.error(self.app.id.span(), format!("app id {}", message)));
+        }
+        if let Err(message) = check_semver(self.app.version.get_ref()) {
+            errors.push(report.error(self.app.version.span(), format!("version {}", message)));
+        }
+        if let Some(homepage) = &self.app.homepage {
+            if let Err(message) = check_homepage(homepage.get_ref()) {
+                errors.push(report.error(homepage.span(), format!("homepage {}", message)));
+            }
+        }
+        if let Some(icon) = &self.app.icon {
+            let icon_path = self.base_dir.join(icon.get_ref());
+            if !icon_path.is_file() {
+                errors
+                    .push(report.error(icon.span(), format!("icon {:?} is not a file", icon_path)));
+            }
+        }
+
+        if self.sources.is_empty() {
+            errors.push(report.error(None, "at least one [[sources]] entry is required"));
+        }
+        for source in &self.sources {
+            let source_path = self.base_dir.join(source.path.get_ref());
+            if !source_path.exists() {
+                errors.push(report.error(
+                    source.path.span(),
+                    format!("source {:?} does not exist", source_path),
+                ));
+            } else if source_path.is_file() {
+                if let Some(pattern) = source.include.first().or(source.exclude.first()) {
+                    errors.push(report.error(
+                        pattern.span(),
+                        "include and exclude only apply to directory sources",
+                    ));
+                }
+            }
+            if let Some(prefix) = &source.prefix {
+                if let Err(message) = check_archive_path(prefix.get_ref()) {
+                    errors.push(report.error(prefix.span(), format!("prefix {}", message)));
+                }
+            }
+            for pattern in &source.include {
+                if let Err(error) = Glob::new(pattern.get_ref()) {
+                    errors
+                        .push(report.error(pattern.span(), format!("invalid include: {}", error)));
+                }
+            }
+            for pattern in &source.exclude {
+                let mut builder = GitignoreBuilder::new("");
+                if let Err(error) = builder.add_line(None, pattern.get_ref()) {
+                    errors
+                        .push(report.error(pattern.span(), format!("invalid exclude: {}", error)));
+                }
+            }
+        }
+
+        for (name, hook) in self.hooks.all() {
+            if let Some(hook) = hook {
+                errors.push(
+                    report.error(hook.span(), format!("hooks.{} is not supported yet", name)),
+                );
+            }
+        }
+        if let Some(signing) = &self.signing {
+            errors.push(report.error(
+                signing.key.span(),
+                "signing is not supported yet, sign the built exe instead",
+            ));
+        }
+        if *self.install.scope.get_ref() == InstallScope::Machine {
+            errors.push(report.error(
+                self.install.scope.span(),
+                "install.scope \"machine\" is not supported yet, apps install per user",
+            ));
+        }
+
+        let mut payload_paths = vec![];
+        if let Some(entrypoint) = &self.install.entrypoint {
+            payload_paths.push(("entrypoint", entrypoint));
+        }
+        for shortcut in &self.shortcuts {
+            if let Err(message) = check_shortcut_name(shortcut.name.get_ref()) {
+                errors
+                    .push(report.error(shortcut.name.span(), format!("shortcut name {}", message)));
+            }
+            payload_paths.push(("shortcut target", &shortcut.target));
+        }
+        for (name, payload_path) in payload_paths {
+            if let Err(message) = self.check_payload_path(payload_path.get_ref()) {
+                errors.push(report.error(payload_path.span(), format!("{} {}", name, message)));
+            }
+        }
+
+        for preserve in &self.install.preserve {
+            if let Err(message) = check_archive_path(preserve.get_ref()) {
+                errors.push(report.error(preserve.span(), format!("preserve {}", message)));
+            }
+        }
+
+        let compression = &self.compression;
+        if let Some(level) = &compression.level {
+            if let Err(error) = self.archive_options().compression.validate(self.format()) {
+                errors.push(report.error(level.span(), error.to_string()));
+            }
+        }
+        if compression.jobs == Some(0) {
+            errors.push(report.error(None, "compression.jobs must be at least 1"));
+        }
+
+        errors
+    }
+
+    /// Paths inside the payload stay relative and must be provided by a source
+    fn check_payload_path(&self, payload_path: &str) -> Result<(), String> {
+        check_archive_path(payload_path)?;
+        let key: PathBuf = payload_path.split(['/', '\\']).collect();
+        let provided = self.source_mappings().iter().any(|mapping| {
+            mapping
+                .resolve(&key)
+                .is_some_and(|source_path| source_path.is_file())
+        });
+        if provided {
+            Ok(())
+        } else {
+            Err(format!(
+                "{:?} is not a file provided by any source",
+                payload_path
+            ))
+        }
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    pub fn source_mappings(&self) -> Vec<SourceMapping> {
+        self.sources
+            .iter()
+            .map(|source| {
+                let strings = |patterns: &[Spanned<String>]| {
+                    patterns
+                        .iter()
+                        .map(|pattern| pattern.get_ref().clone())
+                        .collect()
+                };
+                SourceMapping {
+                    path: self.base_dir.join(source.path.get_ref()),
+                    prefix: source
+                        .prefix
+                        .as_ref()
+                        .map(|prefix| prefix.get_ref().clone())
+                        .unwrap_or_default(),
+                    include: strings(&source.include),
+                    exclude: strings(&source.exclude),
+                }
+            })
+            .collect()
+    }
+
+    pub fn entry_point(&self) -> Option<PathBuf> {
+        self.install
+            .entrypoint
+            .as_ref()
+            .map(|entrypoint| PathBuf::from(entrypoint.get_ref()))
+    }
+
+    /// What installers show and record, see `metadata`
+    pub fn metadata(&self) -> AppMetadata {
+        let string =
+            |value: &Option<Spanned<String>>| value.as_ref().map(|value| value.get_ref().clone());
+        AppMetadata {
+            display_name: string(&self.app.display_name),
+            version: self.app.version.get_ref().clone(),
+            publisher: string(&self.app.publisher),
+            homepage: string(&self.app.homepage),
+            license: self.license_text.clone(),
+        }
+    }
+
+    pub fn icon(&self) -> Option<PathBuf> {
+        self.app
+            .icon
+            .as_ref()
+            .map(|icon| self.base_dir.join(icon.get_ref()))
+    }
+
+    pub fn preserve(&self) -> Vec<String> {
+        self.install
+            .preserve
+            .iter()
+            .map(|preserve| preserve.get_ref().replace('\\', "/"))
+            .collect()
+    }
+
+    pub fn format(&self) -> ArchiveFormat {
+        *self.compression.format.get_ref()
+    }
+
+    /// Include and exclude patterns are per source, see `source_mappings`
+    pub fn archive_options(&self) -> ArchiveOptions {
+        let mut compression = CompressionOptions {
+            method: *self.compression.method.get_ref(),
+            level: self
+                .compression
+                .level
+                .as_ref()
+                .map(|level| *level.get_ref()),
+            ..Default::default()
+        };
+        if !self.compression.store_rules {
+            compression.stored_extensions.clear();
+            compression.entropy_probe = false;
+        }
+        compression.stored_extensions.extend(
+            self.compression
+                .store_extensions
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_ascii_lowercase()),
+        );
+
+        ArchiveOptions {
+            include: vec![],
+            exclude: vec![],
+            reproducible: self.compression.reproducible,
+            compression,
+            jobs: self.compression.jobs,
+        }
+    }
+
+    /// The manifest as embedded under `MANIFEST_KEY`: defaults filled in, store extensions
+    /// lowercased without the dot, payload paths `/`-separated. Signing, license and icon
+    /// files are left out, they only exist on the build machine; license text and icon
+    /// are embedded by `SfxZipBuilder`.
+    pub fn normalized(&self) -> String {
+        let mut manifest = self.clone();
+        manifest.signing = None;
+        manifest.app.license = None;
+        manifest.app.icon = None;
+
+        let slashes = |path: &mut Spanned<String>| {
+            *path.get_mut() = path.get_ref().replace('\\', "/");
+        };
+        manifest.sources.iter_mut().for_each(|source| {
+            slashes(&mut source.path);
+            if let Some(prefix) = &mut source.prefix {
+                *prefix.get_mut() = prefix
+                    .get_ref()
+                    .split(['/', '\\'])
+                    .filter(|part| !part.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("/");
+            }
+        });
+        if let Some(entrypoint) = &mut manifest.install.entrypoint {
+            slashes(entrypoint);
+        }
+        manifest.install.preserve.iter_mut().for_each(slashes);
+        for hook in [
+            &mut manifest.hooks.pre_install,
+            &mut manifest.hooks.post_install,
+            &mut manifest.hooks.pre_uninstall,
+            &mut manifest.hooks.post_uninstall,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            slashes(hook);
+        }
+        manifest
+            .shortcuts
+            .iter_mut()
+            .for_each(|shortcut| slashes(&mut shortcut.target));
+
+        let compression = &mut manifest.compression;
+        compression.store_extensions = compression
+            .store_extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.').to_ascii_lowercase())
+            .collect();
+
+        toml::to_string(&manifest).unwrap()
+    }
+
+    /// Reads back an embedded normalized manifest. Shortcuts get the checks `parse` does,
+    /// short of looking for their targets on disk.
+    pub fn from_normalized(text: &str) -> Result<Manifest, Error> {
+        let manifest: Manifest = toml::from_str(text)
+            .map_err(|error| Error::new(ErrorKind::InvalidData, error.to_string()))?;
+        for shortcut in &manifest.shortcuts {
+            let name = shortcut.name.get_ref();
+            let target = shortcut.target.get_ref();
+            let checked = check_shortcut_name(name)
+                .map_err(|message| format!("shortcut name {:?} {}", name, message))
+                .and_then(|()| {
+                    if target.is_empty() {
+                        return Err(String::from("shortcut target cannot be empty"));
+                    }
+                    check_archive_path(target)
+                        .map_err(|message| format!("shortcut target {:?} {}", target, message))
+                });
+            if let Err(message) = checked {
+                return Err(Error::new(ErrorKind::InvalidData, message));
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// Renders errors pointing into the manifest text
+struct Report<'a> {
+    path: &'a Path,
+    text: &'a str,
+}
+
+impl Report<'_> {
+    fn error<M: Into<String>>(&self, span: impl Into<Option<Range<usize>>>, message: M) -> Error {
+        let message = message.into();
+        let span = match span.into() {
+            // Defaults carry an empty span at 0, they have no place in the text
+            Some(span) if span.end > 0 => span,
+            _ => {
+                return Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}\n --> {}", message, self.path.display()),
+                )
+            }
+        };
+
+        let line_start = self.text[..span.start]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+        let line_end = self.text[span.start..]
+            .find('\n')
+            .map_or(self.text.len(), |index| span.start + index);
+        let line_number = self.text[..span.start].matches('\n').count() + 1;
+        let line = self.text[line_start..line_end].trim_end_matches('\r');
+        let column = self.text[line_start..span.start].chars().count();
+        let width = self.text[span.start..span.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+        let gutter = " ".repeat(line_number.to_string().len());
+
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+                message,
+                gutter,
+                self.path.display(),
+                line_number,
+                column + 1,
+                gutter,
+                line_number,
+                line,
+                gutter,
+                " ".repeat(column),
+                "^".repeat(width)
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Result<Manifest, Vec<Error>> {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("dist")).unwrap();
+        fs::write(dir.path().join("dist").join("app.exe"), "").unwrap();
+        let path = dir.path().join(MANIFEST_FILE_NAME);
+        fs::write(&path, toml).unwrap();
+        Manifest::load(path)
+    }
+
+    const MINIMAL: &str = r#"
+[app]
+id = "com.example.app"
+version = "1.2.0"
+
+[[sources]]
+path = "dist"
+"#;
+
+    fn messages(errors: Vec<Error>) -> Vec<String> {
+        errors.iter().map(|error| error.to_string()).collect()
+    }
+
+    #[test]
+    fn minimal_manifest_gets_defaults() {
+        let manifest = parse(MINIMAL).unwrap();
+        assert_eq!(manifest.format(), ArchiveFormat::Zip);
+        assert_eq!(
+            manifest.archive_options().compression.method,
+            CompressionOptions::default().method
+        );
+        assert_eq!(*manifest.install.scope.get_ref(), InstallScope::User);
+    }
+
+    #[test]
+    fn reserved_keys_are_rejected_where_they_are() {
+        let toml = format!(
+            "{}\n[install]\nscope = \"machine\"\n\n[hooks]\npost_install = \"app.exe\"\n\n[signing]\nkey = \"key.pfx\"\n",
+            MINIMAL
+        );
+        let errors = messages(parse(&toml).unwrap_err());
+        assert_eq!(errors.len(), 3, "{:#?}", errors);
+        assert!(errors[0].starts_with("hooks.post_install is not supported yet"));
+        assert!(errors[0].contains(":13:16"), "{}", errors[0]);
+        assert!(errors[1].starts_with("signing is not supported yet"));
+        assert!(errors[2].starts_with("install.scope \"machine\" is not supported yet"));
+        assert!(errors[2].contains(":10:9"), "{}", errors[2]);
+    }
+
+    #[test]
+    fn unknown_format_and_method_fail_to_load() {
+        let toml = format!("{}\n[compression]\nformat = \"tar.gz\"\n", MINIMAL);
+        let errors = messages(parse(&toml).unwrap_err());
+        assert!(errors[0].contains("unknown archive format \"tar.gz\""));
+        assert!(errors[0].contains(":10:10"), "{}", errors[0]);
+
+        let toml = format!("{}\n[compression]\nmethod = \"Deflate\"\n", MINIMAL);
+        let errors = messages(parse(&toml).unwrap_err());
+        assert!(errors[0].contains("unknown compression method \"Deflate\""));
+    }
+
+    #[test]
+    fn level_is_checked_against_the_format() {
+        let toml = format!(
+            "{}\n[compression]\nformat = \"tar.zst\"\nlevel = 19\n",
+            MINIMAL
+        );
+        assert!(parse(&toml).is_ok());
+
+        let toml = format!("{}\n[compression]\nlevel = 19\n", MINIMAL);
+        let errors = messages(parse(&toml).unwrap_err());
+        assert!(errors[0].contains("out of range for deflate"));
+    }
+
+    #[test]
+    fn normalized_reads_back() {
+        let toml = format!(
+            "{}\n[compression]\nformat = \"tar.xz\"\nmethod = \"zstd\"\nstore_extensions = [\".BIN\"]\n",
+            MINIMAL
+        );
+        let normalized = parse(&toml).unwrap().normalized();
+        let manifest = Manifest::from_normalized(&normalized).unwrap();
+        assert_eq!(manifest.format(), ArchiveFormat::TarXz);
+        assert_eq!(manifest.archive_options().compression.method, Method::Zstd);
+        assert_eq!(manifest.compression.store_extensions, vec!["bin"]);
+
+        assert!(Manifest::from_normalized(&normalized.replace("tar.xz", "rar")).is_err());
+    }
+
+    #[test]
+    fn normalized_shortcuts_are_checked() {
+        let toml = format!(
+            "{}
+[[shortcuts]]
+name = \"Example\"\ntarget = \"app.exe\"\n",
+            MINIMAL
+        );
+        let normalized = parse(&toml).unwrap().normalized();
+        assert!(Manifest::from_normalized(&normalized).is_ok());
+
+        for (from, to, expected) in [
+            (
+                "\"Example\"",
+                "\"Ex/ample\"",
+                "shortcut name \"Ex/ample\" cannot contain '/'",
+            ),
+            (
+                "\"Example\"",
+                "\" \"",
+                "shortcut name \" \" cannot be empty",
+            ),
+            (
+                "\"app.exe\"",
+                "\"../app.exe\"",
+                "shortcut target \"../app.exe\" must not",
+            ),
+            ("\"app.exe\"", "\"\"", "shortcut target cannot be empty"),
+        ] {
+            let error = Manifest::from_normalized(&normalized.replace(from, to)).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+            assert!(error.to_string().starts_with(expected), "{}", error);
+        }
+    }
+}
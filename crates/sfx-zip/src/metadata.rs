@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use sfx_ll::{extractor, version_info::VersionInfo};
+use std::io::{Error, ErrorKind};
+
+/// Custom string holding `AppMetadata` as JSON
+pub const METADATA_KEY: &str = "metadata";
+/// Custom data holding the app icon, an .ico file
+pub const ICON_KEY: &str = "icon";
+
+/// What an installer shows about the app before installing it and records with it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Semantic version, see `check_semver`
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    /// The license text itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+}
+
+impl AppMetadata {
+    /// Metadata embedded in the running exe, `None` for exes built without it
+    pub fn read() -> Result<Option<AppMetadata>, Error> {
+        match extractor::read_custom_string(METADATA_KEY) {
+            Some(metadata) => AppMetadata::from_json(&metadata).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<AppMetadata, Error> {
+        serde_json::from_str(json).map_err(|error| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid app metadata: {}", error),
+            )
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Every problem found, each message naming the field
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors = vec![];
+        if let Err(message) = check_semver(&self.version) {
+            errors.push(Error::new(
+                ErrorKind::InvalidInput,
+                format!("version {:?} {}", self.version, message),
+            ));
+        }
+        if let Some(homepage) = &self.homepage {
+            if let Err(message) = check_homepage(homepage) {
+                errors.push(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("homepage {:?} {}", homepage, message),
+                ));
+            }
+        }
+        errors
+    }
+
+    /// Version resource of the exe. Version parts beyond 65535 are capped,
+    /// the resource keeps 16 bits per part.
+    pub fn version_info(&self) -> VersionInfo {
+        let (major, minor, patch) = check_semver(&self.version).unwrap_or_default();
+        let part = |number: u64| number.min(u16::MAX as u64) as u16;
+        let numbers = [part(major), part(minor), part(patch), 0];
+
+        let mut strings = vec![
+            (String::from("FileVersion"), self.version.clone()),
+            (String::from("ProductVersion"), self.version.clone()),
+        ];
+        if let Some(display_name) = &self.display_name {
+            strings.push((String::from("FileDescription"), display_name.clone()));
+            strings.push((String::from("ProductName"), display_name.clone()));
+        }
+        if let Some(publisher) = &self.publisher {
+            strings.push((String::from("CompanyName"), publisher.clone()));
+        }
+        if let Some(homepage) = &self.homepage {
+            strings.push((String::from("Comments"), homepage.clone()));
+        }
+
+        VersionInfo {
+            file_version: numbers,
+            product_version: numbers,
+            strings,
+        }
+    }
+}
+
+/// Installers show it as a link, so nothing but web URLs
+pub fn check_homepage(homepage: &str) -> Result<(), String> {
+    match homepage.split_once("://") {
+        Some(("http" | "https", rest)) if !rest.is_empty() => Ok(()),
+        _ => Err(String::from("must be an http:// or https:// URL")),
+    }
+}
+
+/// `MAJOR.MINOR.PATCH` with optional `-pre.release` and `+build` parts, as in 2.0.0-rc.1,
+/// following semver.org. Returns the three numbers.
+pub fn check_semver(version: &str) -> Result<(u64, u64, u64), String> {
+    let (version, build) = match version.split_once('+') {
+        Some((version, build)) => (version, Some(build)),
+        None => (version, None),
+    };
+    let (core, pre_release) = match version.split_once('-') {
+        Some((core, pre_release)) => (core, Some(pre_release)),
+        None => (version, None),
+    };
+
+    let numbers = core.split('.').collect::<Vec<_>>();
+    if numbers.len() != 3
+        || numbers
+            .iter()
+            .any(|number| number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()))
+    {
+        return Err(String::from(
+            "must be a semantic version, MAJOR.MINOR.PATCH as in 1.2.0",
+        ));
+    }
+    if let Some(number) = numbers
+        .iter()
+        .find(|number| number.len() > 1 && number.starts_with('0'))
+    {
+        return Err(format!("must not have leading zeros, found {:?}", number));
+    }
+    let number = |number: &str| {
+        number
+            .parse::<u64>()
+            .map_err(|_| format!("has a part too large, {:?}", number))
+    };
+
+    for (name, identifiers) in [("pre-release", pre_release), ("build", build)] {
+        let identifiers = match identifiers {
+            Some(identifiers) => identifiers,
+            None => continue,
+        };
+        let valid_identifier = |identifier: &str| {
+            let numeric = identifier.bytes().all(|byte| byte.is_ascii_digit());
+            !identifier.is_empty()
+                && identifier
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+                // Numeric pre-release identifiers compare as numbers, no leading zeros
+                && !(name == "pre-release"
+                    && numeric
+                    && identifier.len() > 1
+                    && identifier.starts_with('0'))
+        };
+        if !identifiers.split('.').all(valid_identifier) {
+            return Err(format!(
+                "must have a {} part of dot separated ASCII letters, digits and '-', found {:?}",
+                name, identifiers
+            ));
+        }
+    }
+
+    Ok((
+        number(numbers[0])?,
+        number(numbers[1])?,
+        number(numbers[2])?,
+    ))
+}
@@ -0,0 +1,324 @@
+use crate::archive_format::{ArchiveFormat, FORMAT_KEY};
+use crate::entrypoint::{self, ENTRYPOINT_KEY};
+use crate::progress::{Phase, Progress, Tracker};
+use fs2::FileExt;
+use same_file::Handle;
+use sfx_ll::extractor;
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Read},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use walkdir::WalkDir;
+
+/// Flag marking an exe that extracts itself into the cache and runs its entrypoint
+pub const ONEFILE_KEY: &str = "onefile";
+/// sha256 of the payload, names the cache entry
+pub const PAYLOAD_HASH_KEY: &str = "payload_hash";
+/// Overrides the cache location
+pub const CACHE_DIR_ENV: &str = "SFX_ZIP_CACHE_DIR";
+
+/// Least recently used entries are evicted beyond these limits
+pub const CACHE_MAX_ENTRIES: usize = 8;
+pub const CACHE_MAX_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+const APP_SUBPATH: &str = "app";
+const COMPLETE_MARKER: &str = ".complete";
+const LAST_USED_MARKER: &str = ".last-used";
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+pub fn is_onefile() -> bool {
+    extractor::read_custom_string(ONEFILE_KEY).is_some()
+}
+
+/// Per-user cache root: `SFX_ZIP_CACHE_DIR`, else the platform's cache directory
+pub fn cache_root() -> Result<PathBuf, Error> {
+    if let Some(dir) = env::var_os(CACHE_DIR_ENV) {
+        return Ok(PathBuf::from(dir));
+    }
+
+    let platform_cache = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+
+    match platform_cache {
+        Some(dir) => Ok(dir.join("sfx-zip").join("cache")),
+        None => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("cannot find a cache directory, set {}", CACHE_DIR_ENV),
+        )),
+    }
+}
+
+pub fn hash_file<P: AsRef<Path>>(path: P, progress: &mut dyn Progress) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut tracker = Tracker::new(progress, Phase::Hashing, Some(file.metadata()?.len()));
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        tracker.advance(read as u64);
+    }
+    tracker.finish();
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Extracts this exe's payload into the cache unless a previous run already did,
+/// then runs the entrypoint with `args` and returns its exit code.
+pub fn run(args: &[String]) -> Result<i32, Error> {
+    let payload_hash = extractor::read_custom_string(PAYLOAD_HASH_KEY)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "onefile exe has no payload hash"))?;
+    let entry_point = extractor::read_custom_string(ENTRYPOINT_KEY)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "onefile exe has no entrypoint"))?;
+    let format = ArchiveFormat::from_metadata(extractor::read_custom_string(FORMAT_KEY))?;
+
+    let root = cache_root()?;
+    fs::create_dir_all(&root)?;
+    let entry_path = root.join(&payload_hash);
+    let app_path = entry_path.join(APP_SUBPATH);
+
+    // Shared while running so other launches of the same exe start right away and
+    // eviction skips this entry, exclusive only while populating so it happens once.
+    let mut populated = false;
+    let _lock = loop {
+        let lock = lock_entry(&root, &payload_hash, FileExt::lock_shared)?;
+        if entry_path.join(COMPLETE_MARKER).exists() {
+            break lock;
+        }
+        drop(lock);
+
+        let lock = lock_entry(&root, &payload_hash, FileExt::lock_exclusive)?;
+        if !entry_path.join(COMPLETE_MARKER).exists() {
+            populate(&entry_path, format)?;
+            populated = true;
+        }
+        // Evicted before the shared lock is back, the marker check above catches it
+        drop(lock);
+    };
+    fs::write(entry_path.join(LAST_USED_MARKER), now().to_string())?;
+
+    // Only populating grows the cache
+    if populated {
+        if let Err(error) = evict(&root, &payload_hash) {
+            eprintln!("warning: cache cleanup failed: {}", error);
+        }
+    }
+
+    let entry_point = entrypoint::resolve(&app_path, &entry_point)?;
+    entrypoint::run(entry_point, args)
+}
+
+fn populate(entry_path: &Path, format: ArchiveFormat) -> Result<(), Error> {
+    if entry_path.exists() {
+        // Leftover of an interrupted run
+        fs::remove_dir_all(entry_path)?;
+    }
+    fs::create_dir_all(entry_path)?;
+
+    let partial_path = entry_path.join("app.partial");
+    fs::create_dir(&partial_path)?;
+    format.extract_from_reader(extractor::PayloadReader::new()?, &partial_path)?;
+    fs::rename(&partial_path, entry_path.join(APP_SUBPATH))?;
+
+    File::create(entry_path.join(COMPLETE_MARKER))?;
+    Ok(())
+}
+
+fn lock_path(root: &Path, payload_hash: &str) -> PathBuf {
+    root.join(format!("{}.lock", payload_hash))
+}
+
+/// Opens the entry's lock file and locks it with `lock`. Eviction deletes the lock files
+/// it holds, so a lock that ends up on a deleted file is dropped and taken again on the
+/// file now at the path.
+fn lock_entry(
+    root: &Path,
+    payload_hash: &str,
+    lock: fn(&File) -> Result<(), Error>,
+) -> Result<File, Error> {
+    let path = lock_path(root, payload_hash);
+    loop {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        lock(&file)?;
+        // `None` once the file is deleted
+        let current = Handle::from_path(&path).ok();
+        if current.is_some() && current == Some(Handle::from_file(file.try_clone()?)?) {
+            return Ok(file);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+struct CacheEntry {
+    payload_hash: String,
+    path: PathBuf,
+    last_used: u64,
+    size: u64,
+}
+
+/// Removes least recently used entries until the cache fits `CACHE_MAX_ENTRIES`
+/// and `CACHE_MAX_BYTES`, with their lock files. Entries in use by a running exe are
+/// left alone.
+fn evict(root: &Path, current_hash: &str) -> Result<(), Error> {
+    let mut entries = vec![];
+    let mut lock_files = vec![];
+    for dir_entry in fs::read_dir(root)? {
+        let dir_entry = dir_entry?;
+        let path = dir_entry.path();
+        if !dir_entry.file_type()?.is_dir() {
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "lock")
+            {
+                lock_files.push(path);
+            }
+            continue;
+        }
+        let last_used = fs::read_to_string(path.join(LAST_USED_MARKER))
+            .ok()
+            .and_then(|content| content.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let size = WalkDir::new(&path)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+            .sum();
+        entries.push(CacheEntry {
+            payload_hash: dir_entry.file_name().to_string_lossy().into_owned(),
+            path,
+            last_used,
+            size,
+        });
+    }
+
+    entries.sort_by_key(|entry| entry.last_used);
+    let mut count = entries.len();
+    let mut total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+
+    for entry in entries {
+        if count <= CACHE_MAX_ENTRIES && total_size <= CACHE_MAX_BYTES {
+            break;
+        }
+        if entry.payload_hash == current_hash {
+            continue;
+        }
+
+        let lock = match lock_entry(root, &entry.payload_hash, FileExt::try_lock_exclusive) {
+            Ok(lock) => lock,
+            Err(_) => continue,
+        };
+        fs::remove_dir_all(&entry.path)?;
+        // Removed while held: `lock_entry` retries whoever opened it meanwhile
+        fs::remove_file(lock_path(root, &entry.payload_hash))?;
+        drop(lock);
+
+        count -= 1;
+        total_size -= entry.size;
+    }
+
+    // Left by entries evicted earlier or by launches that failed before populating
+    for path in lock_files {
+        let payload_hash = match path.file_stem() {
+            Some(payload_hash) => payload_hash.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        if root.join(&payload_hash).exists() {
+            continue;
+        }
+        if let Ok(_lock) = lock_entry(root, &payload_hash, FileExt::try_lock_exclusive) {
+            // Checked again under the lock, a launch may have populated it meanwhile
+            if !root.join(&payload_hash).exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(root: &Path, payload_hash: &str, last_used: u64) {
+        fs::create_dir_all(root.join(payload_hash).join(APP_SUBPATH)).unwrap();
+        fs::write(
+            root.join(payload_hash).join(LAST_USED_MARKER),
+            last_used.to_string(),
+        )
+        .unwrap();
+        File::create(lock_path(root, payload_hash)).unwrap();
+    }
+
+    #[test]
+    fn running_entry_lets_other_launches_in_and_keeps_eviction_out() {
+        let root = tempfile::tempdir().unwrap();
+        let _running = lock_entry(root.path(), "app", FileExt::lock_shared).unwrap();
+
+        assert!(lock_entry(root.path(), "app", FileExt::try_lock_shared).is_ok());
+        assert!(lock_entry(root.path(), "app", FileExt::try_lock_exclusive).is_err());
+    }
+
+    #[test]
+    fn evicts_least_recently_used_with_lock_files() {
+        let root = tempfile::tempdir().unwrap();
+        let hashes = (0..CACHE_MAX_ENTRIES + 2)
+            .map(|index| format!("entry{}", index))
+            .collect::<Vec<_>>();
+        for (last_used, payload_hash) in hashes.iter().enumerate() {
+            entry(root.path(), payload_hash, last_used as u64);
+        }
+        File::create(lock_path(root.path(), "evicted-before")).unwrap();
+        let _running = lock_entry(root.path(), &hashes[0], FileExt::lock_shared).unwrap();
+
+        evict(root.path(), hashes.last().unwrap()).unwrap();
+
+        // The oldest is in use, the next two go instead
+        assert!(root.path().join(&hashes[0]).is_dir());
+        for payload_hash in &hashes[1..3] {
+            assert!(!root.path().join(payload_hash).exists());
+            assert!(!lock_path(root.path(), payload_hash).exists());
+        }
+        for payload_hash in &hashes[3..] {
+            assert!(root.path().join(payload_hash).is_dir());
+            assert!(lock_path(root.path(), payload_hash).exists());
+        }
+        assert!(!lock_path(root.path(), "evicted-before").exists());
+    }
+
+    #[test]
+    fn lock_is_taken_again_on_a_recreated_lock_file() {
+        let root = tempfile::tempdir().unwrap();
+        let stale = lock_entry(root.path(), "app", FileExt::lock_exclusive).unwrap();
+        fs::remove_file(lock_path(root.path(), "app")).unwrap();
+
+        // The stale file stays locked, the recreated one is free
+        let lock = lock_entry(root.path(), "app", FileExt::try_lock_exclusive).unwrap();
+        assert!(Handle::from_file(lock).unwrap() != Handle::from_file(stale).unwrap());
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    fmt::Display,
+    io::{self, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Hashing,
+    Compressing,
+    Embedding,
+    Extracting,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Hashing => "hashing",
+            Phase::Compressing => "compressing",
+            Phase::Embedding => "embedding",
+            Phase::Extracting => "extracting",
+        }
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressUpdate<'a> {
+    pub phase: Phase,
+    /// Archive path of the entry being processed, `None` for phases without entries
+    pub entry: Option<&'a str>,
+    /// Uncompressed bytes processed so far in this phase
+    pub bytes_done: u64,
+    /// `None` when the total is not known up front, e.g. extracting a tar payload
+    pub bytes_total: Option<u64>,
+    /// Set on the last update of a phase
+    pub finished: bool,
+}
+
+/// Receives updates from archiving, hashing, embedding and extraction.
+/// Any `FnMut(&ProgressUpdate)` closure is a `Progress`.
+pub trait Progress {
+    fn update(&mut self, update: &ProgressUpdate);
+}
+
+impl<F: FnMut(&ProgressUpdate)> Progress for F {
+    fn update(&mut self, update: &ProgressUpdate) {
+        self(update)
+    }
+}
+
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn update(&mut self, _: &ProgressUpdate) {}
+}
+
+/// Counts bytes for one phase and forwards them to a `Progress`
+pub(crate) struct Tracker<'a> {
+    progress: &'a mut dyn Progress,
+    phase: Phase,
+    bytes_done: u64,
+    bytes_total: Option<u64>,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(
+        progress: &'a mut dyn Progress,
+        phase: Phase,
+        bytes_total: Option<u64>,
+    ) -> Tracker<'a> {
+        Tracker {
+            progress,
+            phase,
+            bytes_done: 0,
+            bytes_total,
+        }
+    }
+
+    /// `entry` is done, adding `bytes` to the count
+    pub(crate) fn entry(&mut self, entry: &str, bytes: u64) {
+        self.bytes_done += bytes;
+        self.send(Some(entry), false);
+    }
+
+    pub(crate) fn advance(&mut self, bytes: u64) {
+        self.bytes_done += bytes;
+        self.send(None, false);
+    }
+
+    pub(crate) fn finish(&mut self) {
+        self.send(None, true);
+    }
+
+    fn send(&mut self, entry: Option<&str>, finished: bool) {
+        self.progress.update(&ProgressUpdate {
+            phase: self.phase,
+            entry,
+            bytes_done: self.bytes_done,
+            bytes_total: self.bytes_total,
+            finished,
+        });
+    }
+}
+
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Draws a bar on stderr.
+/// When stderr is not a terminal, e.g. in CI logs, only the end of each phase is printed.
+pub struct ProgressBar {
+    terminal: bool,
+    last_draw: Option<Instant>,
+}
+
+impl ProgressBar {
+    pub fn new() -> ProgressBar {
+        ProgressBar {
+            terminal: io::stderr().is_terminal(),
+            last_draw: None,
+        }
+    }
+}
+
+impl Default for ProgressBar {
+    fn default() -> Self {
+        ProgressBar::new()
+    }
+}
+
+impl Progress for ProgressBar {
+    fn update(&mut self, update: &ProgressUpdate) {
+        if !update.finished {
+            if !self.terminal {
+                return;
+            }
+            if let Some(last_draw) = self.last_draw {
+                if last_draw.elapsed() < REDRAW_INTERVAL {
+                    return;
+                }
+            }
+        }
+        self.last_draw = (!update.finished).then(Instant::now);
+
+        let mut line = match update.bytes_total {
+            Some(total) if total > 0 => {
+                let ratio = (update.bytes_done as f64 / total as f64).min(1.0);
+                let filled = (ratio * BAR_WIDTH as f64) as usize;
+                format!(
+                    "{:<11} [{}{}] {:>3}% {} / {}",
+                    update.phase,
+                    "#".repeat(filled),
+                    "-".repeat(BAR_WIDTH - filled),
+                    (ratio * 100.0) as u32,
+                    format_bytes(update.bytes_done),
+                    format_bytes(total)
+                )
+            }
+            _ => format!("{:<11} {}", update.phase, format_bytes(update.bytes_done)),
+        };
+        if let (false, Some(entry)) = (update.finished, update.entry) {
+            line.push(' ');
+            line.push_str(entry);
+        }
+
+        let mut stderr = io::stderr().lock();
+        if self.terminal {
+            // \x1b[2K clears what is left of a longer previous line
+            let _ = write!(stderr, "\r\x1b[2K{}", line);
+            if update.finished {
+                let _ = writeln!(stderr);
+            }
+        } else {
+            let _ = writeln!(stderr, "{}", line);
+        }
+        let _ = stderr.flush();
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Adapts a `Progress` to the `(bytes embedded, total)` callback of
+/// `sfx_ll::embedder::embed_binary_as_archive_with_progress`
+pub fn embedding(progress: &mut dyn Progress) -> impl FnMut(u64, u64) + '_ {
+    move |bytes_done, bytes_total| {
+        progress.update(&ProgressUpdate {
+            phase: Phase::Embedding,
+            entry: None,
+            bytes_done,
+            bytes_total: Some(bytes_total),
+            finished: bytes_done == bytes_total,
+        })
+    }
+}
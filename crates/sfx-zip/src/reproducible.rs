@@ -0,0 +1,162 @@
+use std::env;
+
+/// https://reproducible-builds.org/specs/source-date-epoch/
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// 1980-01-01T00:00:00Z, the earliest timestamp a zip entry can hold
+pub const DEFAULT_EPOCH: u64 = 315_532_800;
+
+/// Timestamp stamped on every entry in reproducible mode, in seconds since the unix epoch.
+/// `SOURCE_DATE_EPOCH` wins when it is set and valid.
+pub fn timestamp() -> u64 {
+    match env::var(SOURCE_DATE_EPOCH) {
+        Ok(value) => match value.trim().parse::<u64>() {
+            Ok(seconds) => seconds,
+            Err(_) => {
+                eprintln!(
+                    "warning: ignoring invalid {}={:?}",
+                    SOURCE_DATE_EPOCH, value
+                );
+                DEFAULT_EPOCH
+            }
+        },
+        Err(_) => DEFAULT_EPOCH,
+    }
+}
+
+/// Converts unix seconds into an MS-DOS timestamp, clamped into the range zip supports
+pub fn zip_date_time(unix_seconds: u64) -> zip::DateTime {
+    let seconds_of_day = unix_seconds % 86_400;
+    let (year, month, day) = civil_from_unix(unix_seconds);
+
+    if year < 1980 {
+        return zip::DateTime::default();
+    }
+    if year > 2107 {
+        return zip::DateTime::from_date_and_time(2107, 12, 31, 23, 59, 58).unwrap();
+    }
+
+    zip::DateTime::from_date_and_time(
+        year as u16,
+        month,
+        day,
+        (seconds_of_day / 3600) as u8,
+        (seconds_of_day % 3600 / 60) as u8,
+        (seconds_of_day % 60) as u8,
+    )
+    .unwrap()
+}
+
+/// Converts an MS-DOS timestamp, which carries no timezone, to unix seconds as if it were UTC
+pub fn zip_date_time_to_unix(date_time: zip::DateTime) -> u64 {
+    let days = days_from_civil(date_time.year() as i64, date_time.month(), date_time.day());
+    days as u64 * 86_400
+        + date_time.hour() as u64 * 3600
+        + date_time.minute() as u64 * 60
+        + date_time.second() as u64
+}
+
+/// (year, month, day) in UTC
+pub fn civil_from_unix(unix_seconds: u64) -> (i64, u8, u8) {
+    civil_from_days((unix_seconds / 86_400) as i64)
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u8;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_format::ArchiveFormat;
+    use crate::progress::NoProgress;
+    use crate::zip_fns::ArchiveOptions;
+    use std::{
+        fs::{self, File},
+        path::Path,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    const FILES: [(&str, &str); 4] = [
+        ("app.exe", "binary"),
+        ("lib/core.dll", "library"),
+        ("lib/plugins/extra.dll", "plugin"),
+        ("readme.txt", "read me"),
+    ];
+
+    /// The same files, created in `order` and stamped with `mtime`
+    fn tree(order: &[usize], mtime: u64) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for &index in order {
+            let (name, content) = FILES[index];
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(mtime))
+                .unwrap();
+        }
+        dir
+    }
+
+    fn build(format: ArchiveFormat, source: &Path) -> Vec<u8> {
+        let destination = tempfile::NamedTempFile::new().unwrap();
+        let options = ArchiveOptions {
+            reproducible: true,
+            ..Default::default()
+        };
+        format
+            .archive(source, destination.path(), &options, &mut NoProgress)
+            .unwrap();
+        fs::read(destination.path()).unwrap()
+    }
+
+    #[test]
+    fn builds_of_the_same_tree_are_byte_identical() {
+        let first = tree(&[0, 1, 2, 3], 1_600_000_000);
+        let second = tree(&[3, 2, 1, 0], 1_700_000_000);
+        for format in ArchiveFormat::ALL {
+            assert!(
+                build(format, first.path()) == build(format, second.path()),
+                "{}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn dates_round_trip_through_zip() {
+        let seconds = 1_700_000_000;
+        assert_eq!(zip_date_time_to_unix(zip_date_time(seconds)), seconds);
+        assert_eq!(zip_date_time_to_unix(zip_date_time(0)), DEFAULT_EPOCH);
+    }
+}
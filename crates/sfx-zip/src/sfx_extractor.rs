@@ -0,0 +1,176 @@
+use crate::archive_format::{ArchiveFormat, FORMAT_KEY};
+use crate::builder::{ensure_gone, SfxZipError};
+use crate::entrypoint::{self, ENTRYPOINT_KEY};
+use crate::extract::{ExtractLimits, ExtractOptions, ExtractSummary, OverwritePolicy};
+use crate::progress::Progress;
+use sfx_ll::extractor;
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::PathBuf,
+};
+
+/// Extracts the payload embedded in the running exe.
+///
+/// Without `force` or `overwrite` the destination has to be empty or missing.
+#[derive(Debug, Clone)]
+pub struct SfxZipExtractor {
+    destination: PathBuf,
+    force: bool,
+    overwrite: Option<OverwritePolicy>,
+    options: ExtractOptions,
+}
+
+impl SfxZipExtractor {
+    pub fn new<P: Into<PathBuf>>(destination: P) -> SfxZipExtractor {
+        SfxZipExtractor {
+            destination: destination.into(),
+            force: false,
+            overwrite: None,
+            options: ExtractOptions::default(),
+        }
+    }
+
+    /// Remove the destination before extracting
+    pub fn force(mut self, force: bool) -> SfxZipExtractor {
+        self.force = force;
+        self
+    }
+
+    /// Merge into an existing destination, see `OverwritePolicy`
+    pub fn overwrite(mut self, overwrite: OverwritePolicy) -> SfxZipExtractor {
+        self.overwrite = Some(overwrite);
+        self
+    }
+
+    pub fn only(mut self, only: Vec<String>) -> SfxZipExtractor {
+        self.options.only = only;
+        self
+    }
+
+    pub fn strip_prefix<S: Into<String>>(mut self, strip_prefix: S) -> SfxZipExtractor {
+        self.options.strip_prefix = Some(strip_prefix.into());
+        self
+    }
+
+    pub fn limits(mut self, limits: ExtractLimits) -> SfxZipExtractor {
+        self.options.limits = limits;
+        self
+    }
+
+    /// Entrypoint recorded by `SfxZipBuilder::entry_point`
+    pub fn entry_point(&self) -> Option<String> {
+        extractor::read_custom_string(ENTRYPOINT_KEY)
+    }
+
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors: Vec<Error> = vec![];
+
+        if self.force && self.overwrite.is_some() {
+            errors.push(Error::new(
+                ErrorKind::InvalidInput,
+                "force and overwrite cannot be combined.",
+            ));
+        }
+
+        if self.destination.exists() && !self.force {
+            if !self.destination.is_dir() {
+                errors.push(Error::other("destination is not directory."));
+            } else if self.overwrite.is_none() {
+                match self.destination.read_dir() {
+                    Ok(read_dir) => {
+                        if read_dir.count() > 0 {
+                            errors.push(Error::other("destination is not empty."));
+                        }
+                    }
+                    Err(error) => errors.push(error),
+                }
+            }
+        }
+
+        errors
+    }
+
+    pub fn extract(&self, progress: &mut dyn Progress) -> Result<ExtractSummary, SfxZipError> {
+        let errors = self.validate();
+        if !errors.is_empty() {
+            return Err(SfxZipError::Invalid(errors));
+        }
+
+        if self.force {
+            ensure_gone(&self.destination)?;
+        }
+        fs::create_dir_all(&self.destination)?;
+
+        let format = ArchiveFormat::from_metadata(extractor::read_custom_string(FORMAT_KEY))?;
+        let options = ExtractOptions {
+            overwrite: self.overwrite.unwrap_or_default(),
+            ..self.options.clone()
+        };
+        let summary = format.extract_with_options(
+            extractor::PayloadReader::new()?,
+            &self.destination,
+            &options,
+            progress,
+        )?;
+        Ok(summary)
+    }
+
+    /// Runs the extracted entrypoint with `args` and returns its exit code
+    pub fn run_entry_point(&self, args: &[String]) -> Result<i32, SfxZipError> {
+        let entry_point = self.entry_point().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "this archive has no entrypoint to run.",
+            )
+        })?;
+        let entry_point = entrypoint::resolve(&self.destination, &entry_point)?;
+        Ok(entrypoint::run(entry_point, args)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(extractor: &SfxZipExtractor) -> Vec<String> {
+        let errors = extractor.validate();
+        errors.iter().map(|error| error.to_string()).collect()
+    }
+
+    #[test]
+    fn empty_or_missing_destinations_are_accepted() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(problems(&SfxZipExtractor::new(root.path())).is_empty());
+        assert!(problems(&SfxZipExtractor::new(root.path().join("missing"))).is_empty());
+    }
+
+    #[test]
+    fn non_empty_destinations_need_force_or_overwrite() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("app.exe"), "app").unwrap();
+        let extractor = SfxZipExtractor::new(root.path());
+
+        assert_eq!(problems(&extractor), ["destination is not empty."]);
+        assert!(problems(&extractor.clone().force(true)).is_empty());
+        assert!(problems(&extractor.clone().overwrite(OverwritePolicy::Never)).is_empty());
+        assert_eq!(
+            problems(&extractor.force(true).overwrite(OverwritePolicy::Always)),
+            ["force and overwrite cannot be combined."]
+        );
+    }
+
+    #[test]
+    fn file_destinations_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("app.exe");
+        fs::write(&file, "app").unwrap();
+
+        let extractor = SfxZipExtractor::new(&file);
+        assert_eq!(problems(&extractor), ["destination is not directory."]);
+        assert_eq!(
+            problems(&extractor.overwrite(OverwritePolicy::Always)),
+            ["destination is not directory."]
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+use walkdir::{DirEntry, WalkDir};
+
+/// gitignore-style file read from the source root
+pub const IGNORE_FILE_NAME: &str = ".sfxignore";
+
+pub enum Decision {
+    Include,
+    Excluded,
+    NotIncluded,
+}
+
+/// Decides which entries under a source directory end up in the archive.
+///
+/// `exclude` patterns and the `.sfxignore` file share gitignore semantics.
+/// `include` patterns only apply to files; when none are given every file is included.
+pub struct SourceFilter {
+    include: Option<GlobSet>,
+    exclude: Gitignore,
+}
+
+impl SourceFilter {
+    pub fn new<P: AsRef<Path>>(
+        source: P,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<SourceFilter, Error> {
+        let source = source.as_ref();
+
+        let include = if include.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in include {
+                let glob = Glob::new(pattern).map_err(|error| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid include pattern {:?}: {}", pattern, error),
+                    )
+                })?;
+                builder.add(glob);
+            }
+            Some(
+                builder
+                    .build()
+                    .map_err(|error| Error::new(ErrorKind::InvalidInput, format!("{}", error)))?,
+            )
+        };
+
+        let mut builder = GitignoreBuilder::new(source);
+        let ignore_file_path = source.join(IGNORE_FILE_NAME);
+        if ignore_file_path.is_file() {
+            if let Some(error) = builder.add(&ignore_file_path) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid {}: {}", IGNORE_FILE_NAME, error),
+                ));
+            }
+        }
+        for pattern in exclude {
+            builder.add_line(None, pattern).map_err(|error| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid exclude pattern {:?}: {}", pattern, error),
+                )
+            })?;
+        }
+        let exclude = builder
+            .build()
+            .map_err(|error| Error::new(ErrorKind::InvalidInput, format!("{}", error)))?;
+
+        Ok(SourceFilter { include, exclude })
+    }
+
+    /// `key` is the path relative to the source root
+    pub fn decide(&self, key: &Path, is_dir: bool) -> Decision {
+        if key.as_os_str().is_empty() {
+            return Decision::Include;
+        }
+
+        if !is_dir && key == Path::new(IGNORE_FILE_NAME) {
+            return Decision::Excluded;
+        }
+
+        if self.exclude.matched(key, is_dir).is_ignore() {
+            return Decision::Excluded;
+        }
+
+        match (&self.include, is_dir) {
+            (Some(include), false) if !include.is_match(key) => Decision::NotIncluded,
+            _ => Decision::Include,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SkipSummary {
+    pub excluded_files: Vec<PathBuf>,
+    pub excluded_dirs: Vec<PathBuf>,
+    pub not_included_files: Vec<PathBuf>,
+}
+
+impl SkipSummary {
+    pub fn is_empty(&self) -> bool {
+        self.excluded_files.is_empty()
+            && self.excluded_dirs.is_empty()
+            && self.not_included_files.is_empty()
+    }
+
+    /// Adds the skips of another source, whose paths are relative to `prefix`
+    pub fn merge(&mut self, other: SkipSummary, prefix: &Path) {
+        let prefixed = |paths: Vec<PathBuf>| paths.into_iter().map(|path| prefix.join(path));
+        self.excluded_files.extend(prefixed(other.excluded_files));
+        self.excluded_dirs.extend(prefixed(other.excluded_dirs));
+        self.not_included_files
+            .extend(prefixed(other.not_included_files));
+    }
+
+    pub fn print(&self) {
+        if self.is_empty() {
+            return;
+        }
+        println!(
+            "skipped {} excluded file(s), {} excluded dir(s), {} file(s) not matching --include",
+            self.excluded_files.len(),
+            self.excluded_dirs.len(),
+            self.not_included_files.len()
+        );
+        self.excluded_dirs
+            .iter()
+            .for_each(|path| println!("  excluded dir {:?}", path));
+        self.excluded_files
+            .iter()
+            .for_each(|path| println!("  excluded file {:?}", path));
+        self.not_included_files
+            .iter()
+            .for_each(|path| println!("  not included {:?}", path));
+    }
+}
+
+pub struct SourceEntry {
+    pub path: PathBuf,
+    /// path relative to the source root
+    pub key: PathBuf,
+    pub is_dir: bool,
+    /// size in bytes, 0 for directories
+    pub len: u64,
+}
+
+/// Walks `source` and returns every entry that passes the filter, root excluded.
+/// `sorted` walks directories in file name order, which reproducible archives rely on.
+pub fn collect_entries<P: AsRef<Path>>(
+    source: P,
+    filter: &SourceFilter,
+    sorted: bool,
+) -> Result<(Vec<SourceEntry>, SkipSummary), Error> {
+    let source = source.as_ref();
+    let mut skip_summary = SkipSummary::default();
+
+    let walkdir = if sorted {
+        WalkDir::new(source).sort_by_file_name()
+    } else {
+        WalkDir::new(source)
+    };
+    let walkdir_iter = walkdir.into_iter().filter_entry(|entry| {
+        let key = entry.path().strip_prefix(source).unwrap();
+        let is_dir = is_dir(entry);
+        match filter.decide(key, is_dir) {
+            Decision::Include => true,
+            Decision::Excluded if is_dir => {
+                skip_summary.excluded_dirs.push(key.to_path_buf());
+                false
+            }
+            Decision::Excluded => {
+                skip_summary.excluded_files.push(key.to_path_buf());
+                false
+            }
+            Decision::NotIncluded => {
+                skip_summary.not_included_files.push(key.to_path_buf());
+                false
+            }
+        }
+    });
+
+    let mut entries = vec![];
+    for entry in walkdir_iter {
+        let entry = entry?;
+        let key = entry.path().strip_prefix(source).unwrap().to_path_buf();
+        // Only if not root! Avoids path spec / warning
+        // and mapname conversion failed error on unzip
+        if key.as_os_str().is_empty() {
+            continue;
+        }
+        let is_dir = is_dir(&entry);
+        entries.push(SourceEntry {
+            len: if is_dir {
+                0
+            } else {
+                entry.path().metadata()?.len()
+            },
+            is_dir,
+            path: entry.path().to_path_buf(),
+            key,
+        });
+    }
+
+    Ok((entries, skip_summary))
+}
+
+/// Symlinks are not followed by the walk, they are archived as what they point to
+fn is_dir(entry: &DirEntry) -> bool {
+    if entry.path_is_symlink() {
+        entry.path().is_dir()
+    } else {
+        entry.file_type().is_dir()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn tree(files: &[&str]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for file in files {
+            let path = root.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+        root
+    }
+
+    fn collect(root: &Path, include: &[&str], exclude: &[&str]) -> (Vec<String>, SkipSummary) {
+        let strings =
+            |patterns: &[&str]| patterns.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let filter = SourceFilter::new(root, &strings(include), &strings(exclude)).unwrap();
+        let (entries, skip_summary) = collect_entries(root, &filter, true).unwrap();
+        let keys = entries
+            .iter()
+            .map(|entry| {
+                let key = entry.key.to_str().unwrap().replace('\\', "/");
+                if entry.is_dir {
+                    key + "/"
+                } else {
+                    key
+                }
+            })
+            .collect();
+        (keys, skip_summary)
+    }
+
+    #[test]
+    fn excluded_dirs_are_not_walked() {
+        let root = tree(&[
+            "app.exe",
+            "build/out.o",
+            "build/deep/more.o",
+            "src/build.rs",
+        ]);
+        let (keys, skip_summary) = collect(root.path(), &[], &["build/"]);
+
+        assert_eq!(keys, ["app.exe", "src/", "src/build.rs"]);
+        assert_eq!(skip_summary.excluded_dirs, [PathBuf::from("build")]);
+        assert!(skip_summary.excluded_files.is_empty());
+    }
+
+    #[test]
+    fn include_patterns_never_drop_directories() {
+        let root = tree(&[
+            "readme.txt",
+            "docs/guide.txt",
+            "docs/logo.png",
+            "empty/app.exe",
+        ]);
+        let (keys, skip_summary) = collect(root.path(), &["*.txt"], &[]);
+
+        assert_eq!(keys, ["docs/", "docs/guide.txt", "empty/", "readme.txt"]);
+        assert_eq!(
+            skip_summary.not_included_files,
+            [
+                PathBuf::from("docs/logo.png"),
+                PathBuf::from("empty/app.exe")
+            ]
+        );
+    }
+
+    #[test]
+    fn exclude_patterns_come_after_the_ignore_file() {
+        let root = tree(&["app.exe", "debug.log", "keep.log", "cache/index"]);
+        fs::write(root.path().join(IGNORE_FILE_NAME), "*.log\ncache/\n").unwrap();
+
+        let (keys, skip_summary) = collect(root.path(), &[], &["!keep.log", "app.exe"]);
+        assert_eq!(keys, ["keep.log"]);
+        assert_eq!(skip_summary.excluded_dirs, [PathBuf::from("cache")]);
+        assert_eq!(
+            skip_summary.excluded_files,
+            [
+                PathBuf::from(IGNORE_FILE_NAME),
+                PathBuf::from("app.exe"),
+                PathBuf::from("debug.log"),
+            ]
+        );
+
+        // Exclusions beat includes
+        let (keys, _) = collect(root.path(), &["*.log"], &[]);
+        assert!(keys.is_empty(), "{:?}", keys);
+    }
+
+    #[test]
+    fn the_ignore_file_is_never_archived() {
+        let root = tree(&["app.exe", "sub/.sfxignore"]);
+        fs::write(root.path().join(IGNORE_FILE_NAME), "").unwrap();
+
+        let (keys, skip_summary) = collect(root.path(), &["*", ".*"], &["!.sfxignore"]);
+        // Only the one at the root is the filter's own
+        assert_eq!(keys, ["app.exe", "sub/", "sub/.sfxignore"]);
+        assert_eq!(
+            skip_summary.excluded_files,
+            [PathBuf::from(IGNORE_FILE_NAME)]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_dirs_are_dirs() {
+        let root = tree(&["real/app.exe"]);
+        std::os::unix::fs::symlink(root.path().join("real"), root.path().join("link")).unwrap();
+        std::os::unix::fs::symlink(
+            root.path().join("real/app.exe"),
+            root.path().join("app.lnk"),
+        )
+        .unwrap();
+
+        let (keys, _) = collect(root.path(), &[], &[]);
+        assert_eq!(keys, ["app.lnk", "link/", "real/", "real/app.exe"]);
+    }
+}
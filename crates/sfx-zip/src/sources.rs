@@ -0,0 +1,378 @@
+use crate::source_filter::{collect_entries, SkipSummary, SourceEntry, SourceFilter};
+use crate::zip_fns::{archive_key, ArchiveOptions};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{Error, ErrorKind},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+/// Puts a directory or a single file under `prefix` in the archive.
+///
+/// A directory's content lands under the prefix, a file keeps its name inside the prefix.
+/// An empty prefix means the archive root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapping {
+    pub path: PathBuf,
+    /// `/`-separated archive directory
+    pub prefix: String,
+    /// Applied on top of `ArchiveOptions::include`, directories only
+    pub include: Vec<String>,
+    /// Applied on top of `ArchiveOptions::exclude`, directories only
+    pub exclude: Vec<String>,
+}
+
+impl SourceMapping {
+    pub fn new<P: Into<PathBuf>, S: Into<String>>(path: P, prefix: S) -> SourceMapping {
+        SourceMapping {
+            path: path.into(),
+            prefix: prefix.into(),
+            include: vec![],
+            exclude: vec![],
+        }
+    }
+
+    /// `path` mirrored at the archive root, what a single source always did
+    pub fn root<P: Into<PathBuf>>(path: P) -> SourceMapping {
+        SourceMapping::new(path, "")
+    }
+
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors = vec![];
+
+        if !self.path.exists() {
+            errors.push(Error::new(
+                ErrorKind::NotFound,
+                format!("source {:?} does not exist.", self.path),
+            ));
+        } else if self.path.is_file() && !(self.include.is_empty() && self.exclude.is_empty()) {
+            errors.push(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "source {:?} is a file, include and exclude only apply to directories.",
+                    self.path
+                ),
+            ));
+        }
+
+        if let Err(message) = check_archive_path(&self.prefix) {
+            errors.push(Error::new(
+                ErrorKind::InvalidInput,
+                format!("prefix {:?} {}", self.prefix, message),
+            ));
+        }
+
+        errors
+    }
+
+    fn prefix_path(&self) -> PathBuf {
+        self.prefix
+            .split(['/', '\\'])
+            .filter(|part| !part.is_empty())
+            .collect()
+    }
+
+    /// Where the archive path `key` comes from on disk, if this mapping provides it
+    pub fn resolve(&self, key: &Path) -> Option<PathBuf> {
+        let key = key.strip_prefix(self.prefix_path()).ok()?;
+        if self.path.is_file() {
+            (Some(key.as_os_str()) == self.path.file_name()).then(|| self.path.clone())
+        } else {
+            Some(self.path.join(key))
+        }
+    }
+}
+
+/// `path` or `path=prefix`, e.g. `--source assets=share/assets`
+impl FromStr for SourceMapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mapping = match s.rsplit_once('=') {
+            Some((path, prefix)) => SourceMapping::new(path, prefix),
+            None => SourceMapping::root(s),
+        };
+        check_archive_path(&mapping.prefix).map_err(|message| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("prefix {:?} {}", mapping.prefix, message),
+            )
+        })?;
+        Ok(mapping)
+    }
+}
+
+impl Display for SourceMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.prefix.is_empty() {
+            write!(f, "{}", self.path.display())
+        } else {
+            write!(f, "{} => {}", self.path.display(), self.prefix)
+        }
+    }
+}
+
+/// Paths inside the archive, prefixes included, stay relative and inside it
+pub fn check_archive_path(path: &str) -> Result<(), String> {
+    if path.starts_with(['/', '\\']) || path.contains(':') {
+        return Err(String::from("must be relative to the archive root"));
+    }
+    if path.split(['/', '\\']).any(|part| part == "..") {
+        return Err(String::from("must not contain .."));
+    }
+    Ok(())
+}
+
+/// Which mapping an archive path came from, to name both sides of a conflict
+struct Claim {
+    key: String,
+    is_dir: bool,
+    source: PathBuf,
+}
+
+/// Collects the entries of every mapping into one archive layout.
+///
+/// Directories that several mappings produce are merged. Any other archive path
+/// produced twice is a conflict, compared case-insensitively since extraction on
+/// Windows and macOS could not keep both. All conflicts are reported in one error.
+pub fn collect_sources(
+    sources: &[SourceMapping],
+    options: &ArchiveOptions,
+) -> Result<(Vec<SourceEntry>, SkipSummary), Error> {
+    let mut skip_summary = SkipSummary::default();
+    let mut entries = vec![];
+    let mut claims: HashMap<String, Claim> = HashMap::new();
+    let mut conflicts = vec![];
+
+    for mapping in sources {
+        let prefix = mapping.prefix_path();
+        let mut mapped = vec![];
+
+        if mapping.path.is_file() {
+            let file_name = mapping.path.file_name().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("source {:?} has no file name", mapping.path),
+                )
+            })?;
+            mapped.push(SourceEntry {
+                len: mapping.path.metadata()?.len(),
+                is_dir: false,
+                key: prefix.join(file_name),
+                path: mapping.path.clone(),
+            });
+        } else {
+            let include = [options.include.as_slice(), &mapping.include].concat();
+            let exclude = [options.exclude.as_slice(), &mapping.exclude].concat();
+            let filter = SourceFilter::new(&mapping.path, &include, &exclude)?;
+            let (source_entries, source_skips) =
+                collect_entries(&mapping.path, &filter, options.reproducible)?;
+            mapped.extend(source_entries.into_iter().map(|entry| SourceEntry {
+                key: prefix.join(&entry.key),
+                ..entry
+            }));
+            skip_summary.merge(source_skips, &prefix);
+        }
+
+        for entry in mapped {
+            // Directories above the entry that no source lists itself
+            let mut ancestor = PathBuf::new();
+            let parents = entry.key.parent().into_iter().flat_map(Path::components);
+            for component in parents {
+                if let Component::Normal(name) = component {
+                    ancestor.push(name);
+                    let implied = SourceEntry {
+                        path: mapping.path.clone(),
+                        key: ancestor.clone(),
+                        is_dir: true,
+                        len: 0,
+                    };
+                    if claim(&mut claims, &mut conflicts, &implied, &mapping.path) {
+                        entries.push(implied);
+                    }
+                }
+            }
+
+            if claim(&mut claims, &mut conflicts, &entry, &mapping.path) {
+                entries.push(entry);
+            }
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!(
+                "{} conflicting archive path(s):\n{}",
+                conflicts.len(),
+                conflicts.join("\n")
+            ),
+        ));
+    }
+
+    Ok((entries, skip_summary))
+}
+
+/// Records `entry` as coming from `source`. `false` when it must not be added again,
+/// either because the directory already exists or because it conflicts.
+fn claim(
+    claims: &mut HashMap<String, Claim>,
+    conflicts: &mut Vec<String>,
+    entry: &SourceEntry,
+    source: &Path,
+) -> bool {
+    let key = archive_key(&entry.key);
+    match claims.get(&key.to_lowercase()) {
+        None => {
+            claims.insert(
+                key.to_lowercase(),
+                Claim {
+                    key,
+                    is_dir: entry.is_dir,
+                    source: source.to_path_buf(),
+                },
+            );
+            true
+        }
+        Some(existing) if existing.is_dir && entry.is_dir && existing.key == key => false,
+        Some(existing) => {
+            let kind = |is_dir: bool| if is_dir { "dir" } else { "file" };
+            let conflict = format!(
+                "  {} {:?} from {:?} and {} {:?} from {:?}",
+                kind(existing.is_dir),
+                existing.key,
+                existing.source,
+                kind(entry.is_dir),
+                key,
+                source
+            );
+            // Every entry below a conflicting directory would report it again
+            if !conflicts.contains(&conflict) {
+                conflicts.push(conflict);
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_format::ArchiveFormat;
+    use crate::progress::NoProgress;
+    use std::fs;
+    use walkdir::WalkDir;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn mappings(sources: &[String]) -> Vec<SourceMapping> {
+        sources
+            .iter()
+            .map(|source| source.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn sources_are_combined_into_one_layout() {
+        let root = tempfile::tempdir().unwrap();
+        let build = root.path().join("build");
+        write(&build.join("bin/app.exe"), "exe");
+        write(&build.join("assets/logo.png"), "png");
+        write(&build.join("LICENSE"), "license");
+        let sources = mappings(&[
+            format!("{}", build.join("bin").display()),
+            format!("{}=share/assets", build.join("assets").display()),
+            format!("{}=share", build.join("LICENSE").display()),
+        ]);
+
+        for format in [ArchiveFormat::Zip, ArchiveFormat::TarZstd] {
+            let payload = root.path().join(format!("payload.{}", format));
+            format
+                .archive_sources(&sources, &payload, &Default::default(), &mut NoProgress)
+                .unwrap();
+            let destination = root.path().join(format!("extracted-{}", format));
+            let file = fs::File::open(&payload).unwrap();
+            format
+                .extract_with_options(file, &destination, &Default::default(), &mut NoProgress)
+                .unwrap();
+
+            let mut files = WalkDir::new(&destination)
+                .into_iter()
+                .map(|entry| entry.unwrap())
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| archive_key(entry.path().strip_prefix(&destination).unwrap()))
+                .collect::<Vec<_>>();
+            files.sort();
+            assert_eq!(
+                files,
+                ["app.exe", "share/LICENSE", "share/assets/logo.png"],
+                "{}",
+                format
+            );
+            assert_eq!(
+                fs::read_to_string(destination.join("share/LICENSE")).unwrap(),
+                "license"
+            );
+        }
+    }
+
+    #[test]
+    fn conflicting_sources_are_refused() {
+        let root = tempfile::tempdir().unwrap();
+        let (first, second) = (root.path().join("first"), root.path().join("second"));
+        write(&first.join("bin/app.exe"), "first");
+        write(&second.join("App.exe"), "second");
+        write(&second.join("shared/readme.txt"), "merged");
+        write(&first.join("shared/other.txt"), "merged");
+        let sources = mappings(&[
+            format!("{}", first.display()),
+            format!("{}=bin", second.display()),
+        ]);
+
+        let error = collect_sources(&sources, &Default::default())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        let message = error.to_string();
+        assert!(
+            message.starts_with("1 conflicting archive path(s):"),
+            "{}",
+            message
+        );
+        assert!(message.contains("\"bin/app.exe\""), "{}", message);
+        assert!(message.contains("\"bin/App.exe\""), "{}", message);
+
+        // A file where another source has a directory
+        write(&root.path().join("third/bin"), "file");
+        let sources = mappings(&[
+            format!("{}", first.display()),
+            format!("{}", root.path().join("third").display()),
+        ]);
+        let error = collect_sources(&sources, &Default::default())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("file \"bin\""), "{}", error);
+
+        let payload = root.path().join("payload.zip");
+        let result = ArchiveFormat::Zip.archive_sources(
+            &sources,
+            &payload,
+            &Default::default(),
+            &mut NoProgress,
+        );
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn prefixes_stay_inside_the_archive() {
+        for source in ["dist=../up", "dist=/abs", "dist=C:/windows"] {
+            assert!(source.parse::<SourceMapping>().is_err(), "{}", source);
+        }
+        let mapping = "dist=share/assets".parse::<SourceMapping>().unwrap();
+        assert_eq!(mapping, SourceMapping::new("dist", "share/assets"));
+    }
+}
@@ -0,0 +1,131 @@
+use crate::listing::{EntryInfo, EntryKind};
+use crate::progress::{Phase, Progress, Tracker};
+use crate::reproducible;
+use crate::source_filter::SkipSummary;
+use crate::sources::{collect_sources, SourceMapping};
+use crate::zip_fns::{archive_key, ArchiveOptions};
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+use tar::{EntryType, Header};
+
+/// Writes a tar stream of `source` into `writer`.
+/// Compression is up to the caller, see `archive_format`.
+pub fn archive<W, P>(
+    source: P,
+    writer: W,
+    options: &ArchiveOptions,
+    progress: &mut dyn Progress,
+) -> std::io::Result<SkipSummary>
+where
+    W: Write,
+    P: AsRef<Path>,
+{
+    archive_sources(
+        &[SourceMapping::root(source.as_ref())],
+        writer,
+        options,
+        progress,
+    )
+}
+
+/// Writes a tar stream of every mapping, see `sources::collect_sources`
+pub fn archive_sources<W: Write>(
+    sources: &[SourceMapping],
+    writer: W,
+    options: &ArchiveOptions,
+    progress: &mut dyn Progress,
+) -> std::io::Result<SkipSummary> {
+    let (entries, skip_summary) = collect_sources(sources, options)?;
+    let fixed_mtime = options.reproducible.then(reproducible::timestamp);
+    let total_size = entries.iter().map(|entry| entry.len).sum();
+    let mut tracker = Tracker::new(progress, Phase::Compressing, Some(total_size));
+
+    let mut builder = tar::Builder::new(writer);
+
+    for entry in entries {
+        let metadata = fs::metadata(&entry.path)?;
+        let mtime = match fixed_mtime {
+            Some(mtime) => mtime,
+            None => metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+        };
+
+        let mut header = Header::new_gnu();
+        header.set_mode(0o755);
+        header.set_mtime(mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+
+        let key = archive_key(&entry.key);
+        if entry.is_dir {
+            header.set_entry_type(EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, format!("{}/", key), std::io::empty())?;
+        } else {
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(metadata.len());
+            let source_file = File::open(&entry.path)?;
+            builder.append_data(&mut header, &key, source_file)?;
+        }
+        tracker.entry(&key, entry.len);
+    }
+
+    builder.into_inner()?.flush()?;
+    tracker.finish();
+    Ok(skip_summary)
+}
+
+/// Tar has no index, so the whole stream is decompressed, but nothing is written
+pub fn list<R: Read>(reader: R) -> std::io::Result<Vec<EntryInfo>> {
+    let mut entries = vec![];
+    for_each_entry(reader, &mut |entry_info, _| {
+        entries.push(entry_info);
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+/// Visits every entry in archive order with a reader over its content
+pub fn for_each_entry<R: Read>(
+    reader: R,
+    visit: &mut dyn FnMut(EntryInfo, &mut dyn Read) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        let kind = if entry_type.is_dir() {
+            EntryKind::Dir
+        } else if entry_type.is_symlink() {
+            EntryKind::Symlink
+        } else if entry_type.is_hard_link() {
+            EntryKind::Hardlink
+        } else if entry_type.is_file() || entry_type == EntryType::Continuous {
+            EntryKind::File
+        } else {
+            EntryKind::Special
+        };
+        let entry_info = EntryInfo {
+            path: entry.path()?.to_string_lossy().into_owned(),
+            kind,
+            link_target: entry
+                .link_name()?
+                .map(|link_name| link_name.to_string_lossy().into_owned()),
+            size: header.size()?,
+            compressed_size: None,
+            mode: header.mode().ok(),
+            mtime: header.mtime().ok(),
+            crc32: None,
+        };
+        visit(entry_info, &mut entry)?;
+    }
+    Ok(())
+}
@@ -1,50 +1,257 @@
+use crate::archive_format::{ArchiveFormat, ZipFormat};
+use crate::compression::{CompressionOptions, Method};
+use crate::extract::{self, ExtractOptions};
+use crate::listing::{EntryInfo, EntryKind};
+use crate::progress::{NoProgress, Phase, Progress, Tracker};
+use crate::reproducible;
+use crate::source_filter::{SkipSummary, SourceEntry};
+use crate::sources::{collect_sources, SourceMapping};
+use rayon::prelude::*;
 use std::{
     fs::File,
-    io::{Read, Write},
-    path::Path,
+    io::{Cursor, Error, Read, Seek, Write},
+    path::{Component, Path},
 };
-use walkdir::WalkDir;
 use zip::write::FileOptions;
 
+#[derive(Debug, Default, Clone)]
+pub struct ArchiveOptions {
+    /// glob patterns, a file must match one of them to be archived
+    pub include: Vec<String>,
+    /// gitignore-style patterns, applied on top of the source's `.sfxignore`
+    pub exclude: Vec<String>,
+    /// Produce byte-identical output for identical input:
+    /// entries are sorted by name and stamped with `SOURCE_DATE_EPOCH` (or 1980-01-01).
+    /// Permissions are always normalized to 0o755.
+    pub reproducible: bool,
+    pub compression: CompressionOptions,
+    /// Worker threads used for compression, `None` uses every available core.
+    /// The output does not depend on it.
+    pub jobs: Option<usize>,
+}
+
+impl ArchiveOptions {
+    pub fn jobs(&self) -> usize {
+        self.jobs
+            .unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|jobs| jobs.get())
+                    .unwrap_or(1)
+            })
+            .max(1)
+    }
+}
+
 pub fn archive<P>(source: P, destination: P)
 where
     P: AsRef<Path>,
 {
-    let source = source.as_ref();
-    let walkdir = WalkDir::new(source);
-    let walkdir_iter = walkdir.into_iter();
-    let zip_options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .unix_permissions(0o755);
-    let zip_file = File::create(destination).unwrap();
+    archive_with_options(
+        source,
+        destination,
+        &ArchiveOptions::default(),
+        &mut NoProgress,
+    )
+    .unwrap();
+}
+
+pub fn archive_with_options<P>(
+    source: P,
+    destination: P,
+    options: &ArchiveOptions,
+    progress: &mut dyn Progress,
+) -> std::io::Result<SkipSummary>
+where
+    P: AsRef<Path>,
+{
+    archive_sources(
+        &[SourceMapping::root(source.as_ref())],
+        destination.as_ref(),
+        options,
+        progress,
+    )
+}
+
+/// Archives every mapping into one zip, see `sources::collect_sources`
+pub fn archive_sources(
+    sources: &[SourceMapping],
+    destination: &Path,
+    options: &ArchiveOptions,
+    progress: &mut dyn Progress,
+) -> std::io::Result<SkipSummary> {
+    options.compression.validate(ArchiveFormat::Zip)?;
+    let (entries, skip_summary) = collect_sources(sources, options)?;
+    let total_size = entries.iter().map(|entry| entry.len).sum();
+    let mut tracker = Tracker::new(progress, Phase::Compressing, Some(total_size));
+
+    let fixed_time = options
+        .reproducible
+        .then(|| reproducible::zip_date_time(reproducible::timestamp()));
+    let zip_options = |method: Method| {
+        let zip_options = options
+            .compression
+            .zip_options(method)
+            .unix_permissions(0o755);
+        match fixed_time {
+            Some(fixed_time) => zip_options.last_modified_time(fixed_time),
+            None => zip_options,
+        }
+    };
+    let jobs = options.jobs();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(Error::other)?;
+    let zip_file = File::create(destination)?;
     let mut zip = zip::ZipWriter::new(zip_file);
 
-    for entry in walkdir_iter {
-        let entry = entry.unwrap();
-        let source_file_path = entry.path();
-        let key = source_file_path.strip_prefix(source).unwrap();
-
-        // Write file or directory explicitly
-        // Some unzip tools unzip files with directory paths correctly, some do not!
-        if source_file_path.is_file() {
-            let mut buffer = Vec::new();
-            println!("adding file {:?} as {:?} ...", source_file_path, key);
-            #[allow(deprecated)]
-            zip.start_file_from_path(key, zip_options).unwrap();
-
-            let mut source_file = File::open(source_file_path).unwrap();
-            source_file.read_to_end(&mut buffer).unwrap();
-
-            zip.write_all(&*buffer).unwrap();
-        } else if !key.as_os_str().is_empty() {
-            // Only if not root! Avoids path spec / warning
-            // and mapname conversion failed error on unzip
-            println!("adding dir {:?} as {:?} ...", source_file_path, key);
-            #[allow(deprecated)]
-            zip.add_directory(key.to_str().unwrap(), zip_options)
-                .unwrap();
+    // Workers compress a batch of files into single-entry in-memory zips,
+    // which are then raw-copied into the output in walk order.
+    // Batches bound how much compressed data waits in memory.
+    for batch in batches(&entries, jobs) {
+        let compressed = pool.install(|| {
+            batch
+                .par_iter()
+                .map(|entry| {
+                    if entry.is_dir {
+                        Ok(None)
+                    } else {
+                        compress_entry(entry, options, &zip_options).map(Some)
+                    }
+                })
+                .collect::<Vec<_>>()
+        });
+
+        for (entry, compressed) in batch.iter().zip(compressed) {
+            let key = archive_key(&entry.key);
+
+            // Write file or directory explicitly
+            // Some unzip tools unzip files with directory paths correctly, some do not!
+            match compressed? {
+                Some(bytes) => {
+                    let mut single_entry_zip = zip::ZipArchive::new(Cursor::new(bytes))?;
+                    zip.raw_copy_file(single_entry_zip.by_index_raw(0)?)?;
+                }
+                None => {
+                    zip.add_directory(&key, zip_options(Method::Store))?;
+                }
+            }
+            tracker.entry(&key, entry.len);
         }
     }
+
+    zip.finish()?;
+    tracker.finish();
+    Ok(skip_summary)
+}
+
+/// Upper bound of uncompressed bytes read into memory per batch
+const BATCH_BYTES: u64 = 256 * 1024 * 1024;
+const BATCH_ENTRIES_PER_JOB: usize = 8;
+
+fn batches(entries: &[SourceEntry], jobs: usize) -> Vec<&[SourceEntry]> {
+    let mut batches = vec![];
+    let mut start = 0;
+    let mut bytes = 0;
+    for (index, entry) in entries.iter().enumerate() {
+        bytes += entry.len;
+        let count = index + 1 - start;
+        if count >= jobs * BATCH_ENTRIES_PER_JOB || bytes >= BATCH_BYTES {
+            batches.push(&entries[start..=index]);
+            start = index + 1;
+            bytes = 0;
+        }
+    }
+    if start < entries.len() {
+        batches.push(&entries[start..]);
+    }
+    batches
+}
+
+fn compress_entry<F>(
+    entry: &SourceEntry,
+    options: &ArchiveOptions,
+    zip_options: &F,
+) -> std::io::Result<Vec<u8>>
+where
+    F: Fn(Method) -> FileOptions,
+{
+    let mut buffer = Vec::new();
+    let mut source_file = File::open(&entry.path)?;
+    source_file.read_to_end(&mut buffer)?;
+
+    let method = options.compression.method_for(&entry.key, &buffer);
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file(archive_key(&entry.key), zip_options(method))?;
+    zip.write_all(&buffer)?;
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Entry name inside the archive, always `/`-separated regardless of the host platform
+pub fn archive_key(key: &Path) -> String {
+    key.components()
+        .filter_map(|component| match component {
+            Component::Normal(os_str) => Some(os_str.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Reads the central directory only, entries are not decompressed
+pub fn list_from_reader<R>(reader: R) -> std::io::Result<Vec<EntryInfo>>
+where
+    R: Read + Seek,
+{
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut entries = Vec::with_capacity(archive.len());
+    for index in 0..archive.len() {
+        entries.push(entry_info(&archive.by_index_raw(index)?));
+    }
+    Ok(entries)
+}
+
+/// Visits every entry in archive order with a reader over its uncompressed content
+pub fn for_each_entry<R>(
+    reader: R,
+    visit: &mut dyn FnMut(EntryInfo, &mut dyn Read) -> std::io::Result<()>,
+) -> std::io::Result<()>
+where
+    R: Read + Seek,
+{
+    let mut archive = zip::ZipArchive::new(reader)?;
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        visit(entry_info(&file), &mut file)?;
+    }
+    Ok(())
+}
+
+/// Symlinks are stored as unix mode `S_IFLNK` with the target as content
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+
+fn entry_info(file: &zip::read::ZipFile) -> EntryInfo {
+    let kind = match file.unix_mode().map(|mode| mode & S_IFMT) {
+        _ if file.is_dir() => EntryKind::Dir,
+        Some(S_IFLNK) => EntryKind::Symlink,
+        // Fifos and devices; archivers that store no file type leave it 0
+        Some(file_type) if ![0, S_IFREG, S_IFDIR].contains(&file_type) => EntryKind::Special,
+        _ => EntryKind::File,
+    };
+    EntryInfo {
+        path: String::from(file.name()),
+        kind,
+        // The target is the entry's content, which listing does not read
+        link_target: None,
+        size: file.size(),
+        compressed_size: Some(file.compressed_size()),
+        mode: file.unix_mode(),
+        mtime: Some(reproducible::zip_date_time_to_unix(file.last_modified())),
+        crc32: Some(file.crc32()),
+    }
 }
 
 pub fn extract<P>(source: P, destination: P)
@@ -52,6 +259,87 @@ where
     P: AsRef<Path>,
 {
     let zip_file = File::open(source).unwrap();
-    let mut archive = zip::ZipArchive::new(zip_file).unwrap();
-    archive.extract(destination).unwrap();
+    extract_from_reader(zip_file, destination).unwrap();
+}
+
+/// Extracts a zip read from anywhere seekable, e.g. `sfx_ll::extractor::PayloadReader`
+pub fn extract_from_reader<R, P>(mut reader: R, destination: P) -> std::io::Result<()>
+where
+    R: Read + Seek,
+    P: AsRef<Path>,
+{
+    extract::extract_entries(
+        &ZipFormat,
+        &mut reader,
+        destination.as_ref(),
+        &ExtractOptions::default(),
+        &mut NoProgress,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Enough files for several batches at any job count
+    fn source_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for index in 0..100 {
+            let sub = dir.path().join(format!("dir{}", index % 7));
+            fs::create_dir_all(&sub).unwrap();
+            let content = format!("file {} ", index).repeat(index * 50);
+            fs::write(sub.join(format!("file{}.txt", index)), content).unwrap();
+        }
+        dir
+    }
+
+    fn archive_with_jobs(source: &Path, jobs: usize, reproducible: bool) -> Vec<u8> {
+        let destination = tempfile::NamedTempFile::new().unwrap();
+        let options = ArchiveOptions {
+            reproducible,
+            jobs: Some(jobs),
+            ..Default::default()
+        };
+        archive_with_options(source, destination.path(), &options, &mut NoProgress).unwrap();
+        fs::read(destination.path()).unwrap()
+    }
+
+    #[test]
+    fn parallel_compression_matches_the_sequential_output() {
+        let source = source_tree();
+        let names = |zip: &[u8]| {
+            list_from_reader(Cursor::new(zip))
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect::<Vec<_>>()
+        };
+        // Walk order, entries are only sorted in reproducible mode
+        let sequential = names(&archive_with_jobs(source.path(), 1, false));
+        assert_eq!(sequential.len(), 107);
+        // Stamped with the build time otherwise, so only reproducible bytes compare
+        let sequential_bytes = archive_with_jobs(source.path(), 1, true);
+
+        for jobs in [2, 3, 8] {
+            let parallel = names(&archive_with_jobs(source.path(), jobs, false));
+            assert_eq!(parallel, sequential, "{} jobs", jobs);
+            let parallel_bytes = archive_with_jobs(source.path(), jobs, true);
+            assert!(parallel_bytes == sequential_bytes, "{} jobs", jobs);
+        }
+    }
+
+    #[test]
+    fn unwritable_destination_is_an_error() {
+        let source = source_tree();
+        let destination = source.path().join("missing").join("out.zip");
+        let result = archive_with_options(
+            source.path(),
+            destination.as_path(),
+            &ArchiveOptions::default(),
+            &mut NoProgress,
+        );
+        assert!(result.is_err());
+    }
 }